@@ -0,0 +1,461 @@
+// Arithmetic for $(( )) and (( )), on i64 unless float_math is set
+//
+// The expression has already been through parameter expansion and command substitution, bare
+// names left in it are read as variables holding expressions of their own.
+use std::{convert::TryFrom, fmt};
+
+use crate::context::Context;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Num {
+    Int(i64),
+    Float(f64),
+}
+
+impl Num {
+    pub fn is_true(self) -> bool {
+        match self {
+            Num::Int(n) => n != 0,
+            Num::Float(f) => f != 0.0,
+        }
+    }
+
+    fn float(self) -> f64 {
+        match self {
+            Num::Int(n) => n as f64,
+            Num::Float(f) => f,
+        }
+    }
+}
+
+impl fmt::Display for Num {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Num::Int(n) => write!(f, "{}", n),
+            Num::Float(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+// Variables can refer to each other, this stops a=a from looping forever
+const MAX_DEPTH: usize = 32;
+
+pub fn eval(ctx: &mut Context, expr: &str) -> Result<Num, String> {
+    eval_depth(ctx, expr, 0)
+}
+
+fn eval_depth(ctx: &mut Context, expr: &str, depth: usize) -> Result<Num, String> {
+    if depth > MAX_DEPTH {
+        return Err("expression recursion level exceeded".to_string());
+    }
+    let float = ctx.config.float_math;
+    let tokens = tokenize(expr, float)?;
+    if tokens.is_empty() {
+        return Ok(Num::Int(0));
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let tree = parser.assign()?;
+    if let Some(tok) = parser.tokens.get(parser.pos) {
+        return Err(format!("syntax error in expression (error token is \"{}\")", tok));
+    }
+    Evaluator { ctx, depth, float }.eval(&tree)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(Num),
+    Name(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "{}", n),
+            Token::Name(n) => write!(f, "{}", n),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+// Longest first, so ** isn't read as two *
+const OPS: &[&str] = &[
+    "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=", "/=", "%=", "+", "-", "*", "/", "%", "<",
+    ">", "!", "~", "&", "|", "^", "=", "?", ":", "(", ")",
+];
+
+fn tokenize(expr: &str, float: bool) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '.' && float) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(Token::Num(number(&text, float)?));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match OPS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    i += op.len();
+                }
+                None => return Err(format!("syntax error: invalid arithmetic operator (error token is \"{}\")", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// Decimal, 0x hex and 0 octal like C, or a float in float mode
+fn number(text: &str, float: bool) -> Result<Num, String> {
+    let int = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else if text.len() > 1 && text.starts_with('0') && !text.contains('.') {
+        i64::from_str_radix(&text[1..], 8)
+    } else {
+        text.parse::<i64>()
+    };
+    match int {
+        Ok(n) if float => Ok(Num::Float(n as f64)),
+        Ok(n) => Ok(Num::Int(n)),
+        Err(_) if float => text.parse::<f64>().map(Num::Float).map_err(|_| invalid_number(text)),
+        Err(_) if text.contains('.') => Err(format!("floats need float_math=true (error token is \"{}\")", text)),
+        Err(_) => Err(invalid_number(text)),
+    }
+}
+
+fn invalid_number(text: &str) -> String {
+    format!("value too great for base or invalid number (error token is \"{}\")", text)
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(Num),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    Assign(String, &'static str, Box<Expr>), // = or an operator like +=
+}
+
+// How tightly a binary operator binds, higher first
+fn precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | "<=" | ">" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        "**" => 11,
+        _ => return None,
+    })
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.peek_op() != Some(op) {
+            return Err(match self.tokens.get(self.pos) {
+                Some(tok) => format!("syntax error: `{}' expected (error token is \"{}\")", op, tok),
+                None => format!("syntax error: `{}' expected", op),
+            });
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // assign := name ('=' | '+=' | ...) assign | cond
+    fn assign(&mut self) -> Result<Expr, String> {
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            if matches!(*op, "=" | "+=" | "-=" | "*=" | "/=" | "%=") {
+                let (name, op) = (name.clone(), *op);
+                self.pos += 2;
+                return Ok(Expr::Assign(name, op, Box::new(self.assign()?)));
+            }
+        }
+        self.cond()
+    }
+
+    // cond := binary ['?' assign ':' cond]
+    fn cond(&mut self) -> Result<Expr, String> {
+        let test = self.binary(1)?;
+        if self.peek_op() != Some("?") {
+            return Ok(test);
+        }
+        self.pos += 1;
+        let yes = self.assign()?;
+        self.expect(":")?;
+        let no = self.cond()?;
+        Ok(Expr::Cond(Box::new(test), Box::new(yes), Box::new(no)))
+    }
+
+    // operators binding at least as tightly as min, ** groups to the right and the rest to the left
+    fn binary(&mut self, min: u8) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some((op, prec)) = self.peek_op().and_then(|op| Some((op, precedence(op)?))) {
+            if prec < min {
+                break;
+            }
+            self.pos += 1;
+            let right = self.binary(if op == "**" { prec } else { prec + 1 })?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek_op() {
+            Some(op) if matches!(op, "-" | "+" | "!" | "~") => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let tok = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match tok {
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::Name(name)) => Ok(Expr::Var(name)),
+            Some(Token::Op("(")) => {
+                let inner = self.assign()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            Some(tok) => Err(format!("syntax error: operand expected (error token is \"{}\")", tok)),
+            None => Err("syntax error: operand expected".to_string()),
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    ctx: &'a mut Context,
+    depth: usize,
+    float: bool,
+}
+
+impl<'a> Evaluator<'a> {
+    fn eval(&mut self, expr: &Expr) -> Result<Num, String> {
+        match expr {
+            Expr::Num(n) => Ok(*n),
+            Expr::Var(name) => self.var(name),
+            Expr::Unary(op, e) => unary(op, self.eval(e)?),
+            // && and || only evaluate the right side when they need to
+            Expr::Binary("&&", l, r) => Ok(bool_num(self.eval(l)?.is_true() && self.eval(r)?.is_true())),
+            Expr::Binary("||", l, r) => Ok(bool_num(self.eval(l)?.is_true() || self.eval(r)?.is_true())),
+            Expr::Binary(op, l, r) => {
+                let l = self.eval(l)?;
+                binary(op, l, self.eval(r)?)
+            }
+            Expr::Cond(test, yes, no) => {
+                if self.eval(test)?.is_true() {
+                    self.eval(yes)
+                } else {
+                    self.eval(no)
+                }
+            }
+            Expr::Assign(name, op, e) => {
+                let mut value = self.eval(e)?;
+                if *op != "=" {
+                    value = binary(&op[..1], self.var(name)?, value)?;
+                }
+                self.ctx.set_var(name, &value.to_string())?;
+                Ok(value)
+            }
+        }
+    }
+
+    // unset and empty variables are 0
+    fn var(&mut self, name: &str) -> Result<Num, String> {
+        match self.ctx.get_var(name).filter(|v| !v.trim().is_empty()) {
+            Some(value) => eval_depth(self.ctx, &value, self.depth + 1),
+            None if self.float => Ok(Num::Float(0.0)),
+            None => Ok(Num::Int(0)),
+        }
+    }
+}
+
+fn bool_num(b: bool) -> Num {
+    Num::Int(b as i64)
+}
+
+fn unary(op: &str, n: Num) -> Result<Num, String> {
+    Ok(match (op, n) {
+        ("-", Num::Int(i)) => Num::Int(i.checked_neg().ok_or_else(overflow)?),
+        ("-", Num::Float(f)) => Num::Float(-f),
+        ("!", n) => bool_num(!n.is_true()),
+        ("~", Num::Int(i)) => Num::Int(!i),
+        ("~", Num::Float(_)) => return Err("bitwise operators need integers".to_string()),
+        (_, n) => n, // unary +
+    })
+}
+
+fn overflow() -> String {
+    "integer overflow".to_string()
+}
+
+fn binary(op: &str, l: Num, r: Num) -> Result<Num, String> {
+    if matches!(op, "/" | "%") && !r.is_true() {
+        return Err("division by zero".to_string());
+    }
+    let (a, b) = match (l, r) {
+        (Num::Int(a), Num::Int(b)) => (a, b),
+        _ => return float_binary(op, l.float(), r.float()),
+    };
+    Ok(Num::Int(match op {
+        "+" => a.checked_add(b).ok_or_else(overflow)?,
+        "-" => a.checked_sub(b).ok_or_else(overflow)?,
+        "*" => a.checked_mul(b).ok_or_else(overflow)?,
+        "/" => a.checked_div(b).ok_or_else(overflow)?,
+        "%" => a.checked_rem(b).ok_or_else(overflow)?,
+        "**" => {
+            if b < 0 {
+                return Err("exponent less than 0".to_string());
+            }
+            u32::try_from(b).ok().and_then(|b| a.checked_pow(b)).ok_or_else(overflow)?
+        }
+        "<<" => u32::try_from(b).ok().and_then(|b| a.checked_shl(b)).ok_or_else(overflow)?,
+        ">>" => u32::try_from(b).ok().and_then(|b| a.checked_shr(b)).ok_or_else(overflow)?,
+        "&" => a & b,
+        "|" => a | b,
+        "^" => a ^ b,
+        _ => return Ok(bool_num(compare(op, a.partial_cmp(&b)))),
+    }))
+}
+
+fn float_binary(op: &str, a: f64, b: f64) -> Result<Num, String> {
+    Ok(Num::Float(match op {
+        "+" => a + b,
+        "-" => a - b,
+        "*" => a * b,
+        "/" => a / b,
+        "%" => a % b,
+        "**" => a.powf(b),
+        "<<" | ">>" | "&" | "|" | "^" => return Err("bitwise operators need integers".to_string()),
+        _ => return Ok(bool_num(compare(op, a.partial_cmp(&b)))),
+    }))
+}
+
+fn compare(op: &str, ord: Option<std::cmp::Ordering>) -> bool {
+    use std::cmp::Ordering::*;
+    match op {
+        "==" => ord == Some(Equal),
+        "!=" => ord != Some(Equal),
+        "<" => ord == Some(Less),
+        "<=" => matches!(ord, Some(Less) | Some(Equal)),
+        ">" => ord == Some(Greater),
+        ">=" => matches!(ord, Some(Greater) | Some(Equal)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(ctx: &mut Context, expr: &str) -> Result<String, String> {
+        eval(ctx, expr).map(|n| n.to_string())
+    }
+
+    #[test]
+    fn test_integers() {
+        let mut ctx = Context::new();
+        let mut ok = |e: &str| calc(&mut ctx, e).unwrap();
+        assert_eq!(ok("1 + 2 * 3"), "7");
+        assert_eq!(ok("(1 + 2) * 3"), "9");
+        assert_eq!(ok("7 / 2"), "3");
+        assert_eq!(ok("-7 % 3"), "-1");
+        assert_eq!(ok("2 ** 3 ** 2"), "512");
+        assert_eq!(ok("-2 ** 2"), "4");
+        assert_eq!(ok("10 - 2 - 3"), "5");
+        assert_eq!(ok("1 < 2 && 2 <= 2 && !(3 == 4)"), "1");
+        assert_eq!(ok("0 || 0"), "0");
+        assert_eq!(ok("6 & 3 | 8 ^ 1"), "11");
+        assert_eq!(ok("1 << 4 >> 2"), "4");
+        assert_eq!(ok("~0"), "-1");
+        assert_eq!(ok("0x1f + 010"), "39");
+        assert_eq!(ok("1 ? 2 : 3"), "2");
+        assert_eq!(ok(""), "0");
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut ctx = Context::new();
+        assert_eq!(calc(&mut ctx, "1 / 0"), Err("division by zero".to_string()));
+        assert_eq!(calc(&mut ctx, "5 % (2 - 2)"), Err("division by zero".to_string()));
+        assert_eq!(calc(&mut ctx, "9223372036854775807 + 1"), Err("integer overflow".to_string()));
+        assert_eq!(calc(&mut ctx, "2 ** 64"), Err("integer overflow".to_string()));
+        assert_eq!(calc(&mut ctx, "-9223372036854775807 - 1").unwrap(), i64::MIN.to_string());
+        assert!(calc(&mut ctx, "2 ** -1").is_err());
+        assert!(calc(&mut ctx, "1 +").is_err());
+        assert!(calc(&mut ctx, "(1").is_err());
+        assert!(calc(&mut ctx, "1 2").is_err());
+        assert!(calc(&mut ctx, "1.5 + 1").is_err());
+        assert!(calc(&mut ctx, "1 $ 2").is_err());
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut ctx = Context::new();
+        ctx.set_var("YUI_TEST_N", "4").unwrap();
+        ctx.set_var("YUI_TEST_EXPR", "YUI_TEST_N * 2").unwrap();
+        ctx.unset_var("YUI_TEST_UNSET").unwrap();
+        assert_eq!(calc(&mut ctx, "YUI_TEST_N + 1").unwrap(), "5");
+        assert_eq!(calc(&mut ctx, "YUI_TEST_EXPR").unwrap(), "8");
+        assert_eq!(calc(&mut ctx, "YUI_TEST_UNSET + 1").unwrap(), "1");
+        assert_eq!(calc(&mut ctx, "YUI_TEST_N += 2").unwrap(), "6");
+        assert_eq!(ctx.get_var("YUI_TEST_N"), Some("6".to_string()));
+        assert_eq!(calc(&mut ctx, "YUI_TEST_A = YUI_TEST_B = 3").unwrap(), "3");
+        assert_eq!(ctx.get_var("YUI_TEST_A"), Some("3".to_string()));
+        // the side that isn't needed isn't evaluated
+        assert_eq!(calc(&mut ctx, "0 && (YUI_TEST_A = 9)").unwrap(), "0");
+        assert_eq!(calc(&mut ctx, "1 ? YUI_TEST_A : (YUI_TEST_A = 9)").unwrap(), "3");
+        ctx.set_var("YUI_TEST_LOOP", "YUI_TEST_LOOP").unwrap();
+        assert!(calc(&mut ctx, "YUI_TEST_LOOP").is_err());
+    }
+
+    #[test]
+    fn test_floats() {
+        let mut ctx = Context::new();
+        ctx.config.float_math = true;
+        assert_eq!(calc(&mut ctx, "7 / 2").unwrap(), "3.5");
+        assert_eq!(calc(&mut ctx, "1.5 * 2").unwrap(), "3");
+        assert_eq!(calc(&mut ctx, ".5 + 0.25").unwrap(), "0.75");
+        assert_eq!(calc(&mut ctx, "2 ** 0.5 > 1.41").unwrap(), "1");
+        assert_eq!(calc(&mut ctx, "1 / 0"), Err("division by zero".to_string()));
+        assert!(calc(&mut ctx, "1.5 & 1").is_err());
+    }
+}
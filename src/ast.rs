@@ -0,0 +1,202 @@
+// Syntax tree produced by the parser and walked by spawn
+//
+// Words are stored as raw text with their quoting intact, expansion happens
+// right before a command is run.
+use std::fmt;
+
+// A sequence of and-or lists separated by ';', '&' or newlines
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct List {
+    pub items: Vec<ListItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListItem {
+    pub and_or: AndOr,
+    pub background: bool, // terminated by '&'
+}
+
+// Pipelines joined by && and ||
+#[derive(Debug, Clone, PartialEq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    And, // &&
+    Or,  // ||
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(CompoundCommand, Vec<Redirect>), // the redirections apply to everything inside
+    Function(FunctionDef),
+}
+
+// name() { list; }, running it only defines the function
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDef {
+    pub name: String,
+    pub body: List,
+}
+
+// Commands made of other commands, they run in the shell itself unless they're part of a pipeline
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompoundCommand {
+    If(IfClause),
+    Arith(String),  // ((expr)), true if expr isn't 0
+    Subshell(List), // ( list ), always runs in a forked copy of the shell
+    Group(List),    // { list; }
+}
+
+// if list; then list; [elif list; then list;]... [else list;] fi
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfClause {
+    pub branches: Vec<(List, List)>, // condition and body, for the if and every elif
+    pub otherwise: Option<List>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SimpleCommand {
+    pub assignments: Vec<(String, String)>, // NAME=value words in front of the command
+    pub words: Vec<String>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: Option<i32>, // explicit fd in front of the operator, eg. 2>
+    pub kind: RedirectKind,
+    pub target: String, // the body for here-documents
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectKind {
+    Input,                    // <
+    HereDoc { expand: bool }, // << and <<-, no expansion if the delimiter was quoted
+    HereString,               // <<<
+    Output,                   // >
+    Append,                   // >>
+    DupInput,                 // <&
+    DupOutput,                // >&
+    OutputBoth,               // &>
+    AppendBoth,               // &>>
+}
+
+// Display gives back shell syntax, used to show what a job is running
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}{}", item.and_or, if item.background { " &" } else { ";" })?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AndOr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in self.rest.iter() {
+            let op = match connector {
+                Connector::And => "&&",
+                Connector::Or => "||",
+            };
+            write!(f, " {} {}", op, pipeline)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, cmd) in self.commands.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", cmd)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Simple(simple) => write!(f, "{}", simple),
+            Command::Compound(compound, redirects) => {
+                write!(f, "{}", compound)?;
+                for r in redirects.iter() {
+                    write!(f, " {}", r)?;
+                }
+                Ok(())
+            }
+            Command::Function(def) => write!(f, "{}", def),
+        }
+    }
+}
+
+impl fmt::Display for FunctionDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}() {{ {} }}", self.name, self.body)
+    }
+}
+
+impl fmt::Display for CompoundCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompoundCommand::If(clause) => {
+                for (i, (cond, body)) in clause.branches.iter().enumerate() {
+                    write!(f, "{} {} then {} ", if i == 0 { "if" } else { "elif" }, cond, body)?;
+                }
+                if let Some(body) = &clause.otherwise {
+                    write!(f, "else {} ", body)?;
+                }
+                write!(f, "fi")
+            }
+            CompoundCommand::Arith(expr) => write!(f, "(({}))", expr),
+            CompoundCommand::Subshell(list) => write!(f, "( {} )", list),
+            CompoundCommand::Group(list) => write!(f, "{{ {} }}", list),
+        }
+    }
+}
+
+impl fmt::Display for SimpleCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts: Vec<String> = self.assignments.iter().map(|(n, v)| format!("{}={}", n, v)).collect();
+        parts.extend(self.words.iter().cloned());
+        parts.extend(self.redirects.iter().map(|r| r.to_string()));
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fd = self.fd.map(|n| n.to_string()).unwrap_or_default();
+        let op = match self.kind {
+            RedirectKind::Input => "<",
+            RedirectKind::HereDoc { .. } => "<<",
+            RedirectKind::HereString => "<<<",
+            RedirectKind::Output => ">",
+            RedirectKind::Append => ">>",
+            RedirectKind::DupInput => "<&",
+            RedirectKind::DupOutput => ">&",
+            RedirectKind::OutputBoth => "&>",
+            RedirectKind::AppendBoth => "&>>",
+        };
+        // heredoc bodies would be too long to show
+        let target = if let RedirectKind::HereDoc { .. } = self.kind { "" } else { self.target.as_str() };
+        write!(f, "{}{}{}", fd, op, target)
+    }
+}
@@ -1,13 +1,14 @@
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::{
-    env,
-    fs::File,
-    io::{BufRead, BufReader, ErrorKind},
-    path::Path,
-};
+use std::{env, io::ErrorKind, path::Path};
 
+use crate::context::Var;
+use crate::expand;
+use crate::histfile::Record;
+use crate::parser;
 use crate::paths;
+use crate::spawn;
+use crate::sys;
 use crate::Context;
 //use crate::ALIASES;
 
@@ -16,73 +17,238 @@ lazy_static! {
     pub static ref CHECK_EQ: Regex = Regex::new(r"^([a-zA-Z0-9_]+)=(.*)$").unwrap();
 }
 
-pub fn cd(d: &[&String]) {
+// Builtins return their exit status
+
+pub fn cd(ctx: &Context, d: &[&String]) -> i32 {
     let new_dir;
     if d.is_empty() {
-        new_dir = paths::get_user_home();
-    } else if d.iter().count() > 1 {
+        new_dir = ctx.home();
+    } else if d.len() > 1 {
         eprintln!("yui: cd: Too many arguments");
-        return;
+        return 1;
     } else {
-        new_dir = d.into_iter().peekable().peek().map_or("/".to_string(), |x| (*x).to_string());
+        new_dir = d.iter().peekable().peek().map_or("/".to_string(), |x| (*x).to_string());
     };
     let final_path = new_dir;
     let root = Path::new(&final_path);
-    if let Err(e) = env::set_current_dir(&root) {
+    if let Err(e) = env::set_current_dir(root) {
         match e.kind() {
             ErrorKind::NotFound => eprintln!("yui: cd: No such file or directory"),
             ErrorKind::PermissionDenied => eprintln!("yui: cd: Permission denied"),
             _ => eprintln!("yui: cd: {}", e),
         }
+        return 1;
     }
+    0
 }
 
-pub fn echo(s: &[&String]) {
+pub fn echo(s: &[&String]) -> i32 {
     let mut to_print = String::new();
     for word in s.iter() {
         to_print.push_str(word);
         to_print.push(' ');
     }
     println!("{}", to_print.trim());
+    0
+}
+
+// export NAME[=VALUE]..., with no names or -p list the exported variables
+pub fn export(ctx: &mut Context, s: &[&String]) -> i32 {
+    if s.is_empty() || (s.len() == 1 && s[0] == "-p") {
+        print_vars(ctx, "export", |v| v.exported);
+        return 0;
+    }
+    set_attribute(ctx, "export", s, |v| v.exported = true)
+}
+
+// readonly NAME[=VALUE]..., with no names or -p list the readonly variables
+pub fn readonly(ctx: &mut Context, s: &[&String]) -> i32 {
+    if s.is_empty() || (s.len() == 1 && s[0] == "-p") {
+        print_vars(ctx, "readonly", |v| v.readonly);
+        return 0;
+    }
+    set_attribute(ctx, "readonly", s, |v| v.readonly = true)
 }
 
-pub fn export(s: &[&String]) {
+// unset NAME..., or unset -f NAME... for functions
+pub fn unset(ctx: &mut Context, s: &[&String]) -> i32 {
+    let mut status = 0;
+    if s.first().filter(|o| **o == "-f").is_some() {
+        for name in s[1..].iter() {
+            ctx.functions.remove(name.as_str());
+        }
+        return 0;
+    }
+    for name in s.iter().filter(|n| **n != "-v") {
+        if !expand::is_name(name) {
+            eprintln!("yui: unset: `{}': not a valid identifier", name);
+            status = 1;
+        } else if let Err(e) = ctx.unset_var(name) {
+            eprintln!("yui: unset: {}", e);
+            status = 1;
+        }
+    }
+    status
+}
+
+// local NAME[=VALUE]..., the variables go back to what they were when the function returns
+pub fn local(ctx: &mut Context, s: &[&String]) -> i32 {
+    let mut status = 0;
     for input in s.iter() {
-        if !CHECK_EQ.is_match(input) {
-            eprintln!("yui: export: invalid usage\n  export OPTION=VALUE");
-            break;
+        let (name, value) = match input.find('=') {
+            Some(eq) => (&input[..eq], Some(&input[eq + 1..])),
+            None => (input.as_str(), None),
+        };
+        if !expand::is_name(name) {
+            eprintln!("yui: local: `{}': not a valid identifier", input);
+            status = 1;
+            continue;
+        }
+        let set = ctx.make_local(name).and_then(|_| match value {
+            Some(v) => ctx.set_var(name, v),
+            None => Ok(()),
+        });
+        if let Err(e) = set {
+            eprintln!("yui: local: {}", e);
+            status = 1;
         }
+    }
+    status
+}
 
-        for cap in CHECK_EQ.captures_iter(input) {
-            let name = cap[1].to_string();
-            let value = paths::expand_home(&cap[2]);
-            env::set_var(name, &value);
+// Stop the function being run, with status n or the last status
+pub fn return_(ctx: &mut Context, s: &[&String]) -> i32 {
+    if ctx.frames.is_empty() {
+        eprintln!("yui: return: can only `return' from a function");
+        return 1;
+    }
+    let status = match s.first() {
+        None => ctx.laststatus,
+        Some(n) => match n.parse::<i64>() {
+            Ok(n) => n.rem_euclid(256) as i32,
+            Err(_) => {
+                eprintln!("yui: return: {}: numeric argument required", n);
+                2
+            }
+        },
+    };
+    ctx.returning = Some(status);
+    status
+}
+
+// Print the definitions of the named functions, or all of them
+pub fn functions(ctx: &Context, s: &[&String]) -> i32 {
+    if s.is_empty() {
+        let mut names: Vec<&String> = ctx.functions.keys().collect();
+        names.sort();
+        for name in names {
+            println!("{}", ctx.functions[name]);
+        }
+        return 0;
+    }
+    let mut status = 0;
+    for name in s.iter() {
+        match ctx.functions.get(name.as_str()) {
+            Some(def) => println!("{}", def),
+            None => {
+                eprintln!("yui: functions: {}: not found", name);
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+// Say what each name would run as a command, in the order they're looked up
+pub fn type_(ctx: &Context, s: &[&String]) -> i32 {
+    let mut status = 0;
+    for name in s.iter() {
+        let name = name.as_str();
+        if let Some(value) = ctx.aliases.get(name) {
+            println!("{} is aliased to `{}'", name, value);
+        } else if parser::is_reserved(name) {
+            println!("{} is a shell keyword", name);
+        } else if let Some(def) = ctx.functions.get(name) {
+            println!("{} is a function\n{}", name, def);
+        } else if spawn::is_builtin(name) {
+            println!("{} is a shell builtin", name);
+        } else if let Some(path) = paths::find_command(name, &ctx.get_var("PATH").unwrap_or_default()) {
+            println!("{} is {}", name, path);
+        } else {
+            eprintln!("yui: type: {}: not found", name);
+            status = 1;
         }
     }
+    status
+}
+
+// Assign the NAME=VALUE arguments and give every named variable an attribute
+fn set_attribute(ctx: &mut Context, cmd: &str, s: &[&String], attr: impl Fn(&mut Var)) -> i32 {
+    let mut status = 0;
+    for input in s.iter() {
+        let (name, value) = match input.find('=') {
+            Some(eq) => (&input[..eq], Some(paths::expand_home(&input[eq + 1..]))),
+            None => (input.as_str(), None),
+        };
+        if !expand::is_name(name) {
+            eprintln!("yui: {}: `{}': not a valid identifier", cmd, input);
+            status = 1;
+            continue;
+        }
+        if let Some(value) = value {
+            if let Err(e) = ctx.set_var(name, &value) {
+                eprintln!("yui: {}", e);
+                status = 1;
+                continue;
+            }
+        }
+        attr(ctx.vars.entry(name.to_string()).or_default());
+    }
+    status
+}
+
+// List variables as the commands that would recreate them
+fn print_vars(ctx: &Context, cmd: &str, filter: impl Fn(&Var) -> bool) {
+    let mut names: Vec<&String> = ctx.vars.iter().filter(|(_, v)| filter(v)).map(|(n, _)| n).collect();
+    names.sort();
+    for name in names {
+        match &ctx.vars[name].value {
+            Some(value) => println!("{} {}={}", cmd, name, quote(value)),
+            None => println!("{} {}", cmd, name),
+        }
+    }
+}
+
+// Single quote s so it reads back as the same word
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
 }
 
-pub fn set(ctx: &mut Context, s: &[&String]) {
+pub fn set(ctx: &mut Context, s: &[&String]) -> i32 {
+    let mut status = 0;
     if s.is_empty() {
         println!("{:#?}", ctx.config)
     } else {
         for input in s.iter() {
             if !CHECK_EQ.is_match(input) {
                 eprintln!("yui: set: invalid usage\n  set OPTION=VALUE");
-                break;
+                return 1;
             }
 
             for cap in CHECK_EQ.captures_iter(input) {
                 let name = cap[1].to_string();
                 let value = paths::expand_home(&cap[2]);
-                if crate::config::convert_and_set_key(ctx, &name, &value) == false {
+                if !crate::config::convert_and_set_key(ctx, &name, &value) {
                     eprintln!("Invalid option: '{}'", name);
+                    status = 1;
                 }
             }
         }
     }
+    status
 }
 
-pub fn alias(ctx: &mut Context, s: &[&String]) {
+pub fn alias(ctx: &mut Context, s: &[&String]) -> i32 {
     if s.is_empty() {
         let map = &mut ctx.aliases;
         if map.is_empty() {
@@ -92,14 +258,14 @@ pub fn alias(ctx: &mut Context, s: &[&String]) {
             for (k, v) in map.iter() {
                 println!("  {}={}", k, v);
             }
-            return;
+            return 0;
         }
     }
     let all = &mut ctx.aliases;
     for input in s.iter() {
         if !CHECK_EQ.is_match(input) {
             eprintln!("yui: alias: invalid usage\n  alias OPTION=VALUE OPTION=VALUE ...");
-            break;
+            return 1;
         }
 
         for cap in CHECK_EQ.captures_iter(input) {
@@ -108,19 +274,431 @@ pub fn alias(ctx: &mut Context, s: &[&String]) {
             all.insert(name, value);
         }
     }
+    0
 }
 
-//pub fn history(num: usize, hist: &String, s: &[&String]) {
-//let pad = num.to_string().len(); // not the optimal way, but it works
-pub fn history(hist: &String, s: &[&String]) {
+pub fn shift(ctx: &mut Context, s: &[&String]) -> i32 {
+    let n = match s.first() {
+        None => 1,
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) => n,
+            Err(_) => {
+                eprintln!("yui: shift: {}: numeric argument required", n);
+                return 1;
+            }
+        },
+    };
+    if n > ctx.args.len() {
+        eprintln!("yui: shift: shift count out of range");
+        return 1;
+    }
+    ctx.args.drain(..n);
+    0
+}
+
+// test EXPR and [ EXPR ], 0 if the expression is true, 1 if false and 2 if it's invalid
+pub fn test(s: &[&String], bracket: bool) -> i32 {
+    let name = if bracket { "[" } else { "test" };
+    let mut args: Vec<&str> = s.iter().map(|a| a.as_str()).collect();
+    if bracket {
+        if args.last() != Some(&"]") {
+            eprintln!("yui: [: missing `]'");
+            return 2;
+        }
+        args.pop();
+    }
+    if args.is_empty() {
+        return 1;
+    }
+    let mut t = Test { args: &args, pos: 0 };
+    let result = t.or().and_then(|r| match t.args.get(t.pos) {
+        Some(extra) => Err(format!("{}: unexpected argument", extra)),
+        None => Ok(r),
+    });
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("yui: {}: {}", name, e);
+            2
+        }
+    }
+}
+
+// Recursive descent over the arguments of test, with -o binding looser than -a and ! tighter
+struct Test<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> Test<'a> {
+    fn next(&mut self) -> Result<&'a str, String> {
+        let arg = self.args.get(self.pos).ok_or_else(|| "argument expected".to_string())?;
+        self.pos += 1;
+        Ok(arg)
+    }
+
+    // an operand followed by a binary operator is a comparison, whatever the operand looks like
+    fn at_binary(&self) -> bool {
+        self.pos + 2 < self.args.len() && is_binary(self.args[self.pos + 1])
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.args.get(self.pos) == Some(&"-o") {
+            self.pos += 1;
+            result = self.and()? || result;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+        while self.args.get(self.pos) == Some(&"-a") {
+            self.pos += 1;
+            result = self.not()? && result;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        if self.args.get(self.pos) == Some(&"!") && self.pos + 1 < self.args.len() && !self.at_binary() {
+            self.pos += 1;
+            return Ok(!self.not()?);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        if self.at_binary() {
+            let left = self.next()?;
+            let op = self.next()?;
+            let right = self.next()?;
+            return binary(left, op, right);
+        }
+        let arg = self.next()?;
+        if arg == "(" && self.pos < self.args.len() {
+            let result = self.or()?;
+            if self.next().ok() != Some(")") {
+                return Err("`)' expected".to_string());
+            }
+            return Ok(result);
+        }
+        if is_unary(arg) && self.pos < self.args.len() {
+            return Ok(unary(arg, self.next()?));
+        }
+        // a lone word is true if it isn't empty
+        Ok(!arg.is_empty())
+    }
+}
+
+fn is_unary(op: &str) -> bool {
+    matches!(op, "-z" | "-n" | "-e" | "-f" | "-d" | "-r" | "-w" | "-x" | "-s" | "-L" | "-h")
+}
+
+fn is_binary(op: &str) -> bool {
+    matches!(op, "=" | "==" | "!=" | "<" | ">" | "-eq" | "-ne" | "-lt" | "-gt" | "-le" | "-ge")
+}
+
+fn unary(op: &str, arg: &str) -> bool {
+    let meta = || std::fs::metadata(arg);
+    match op {
+        "-z" => arg.is_empty(),
+        "-n" => !arg.is_empty(),
+        "-e" => meta().is_ok(),
+        "-f" => meta().map(|m| m.is_file()).unwrap_or(false),
+        "-d" => meta().map(|m| m.is_dir()).unwrap_or(false),
+        "-r" => sys::access(arg, libc::R_OK),
+        "-w" => sys::access(arg, libc::W_OK),
+        "-x" => sys::access(arg, libc::X_OK),
+        "-s" => meta().map(|m| m.len() > 0).unwrap_or(false),
+        "-L" | "-h" => std::fs::symlink_metadata(arg).map(|m| m.file_type().is_symlink()).unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn binary(left: &str, op: &str, right: &str) -> Result<bool, String> {
+    let int = |s: &str| s.trim().parse::<i64>().map_err(|_| format!("{}: integer expression expected", s));
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" => int(left)? == int(right)?,
+        "-ne" => int(left)? != int(right)?,
+        "-lt" => int(left)? < int(right)?,
+        "-gt" => int(left)? > int(right)?,
+        "-le" => int(left)? <= int(right)?,
+        "-ge" => int(left)? >= int(right)?,
+        _ => return Err(format!("{}: binary operator expected", op)),
+    })
+}
+
+// Ask the shell to exit once the current command is done, with status n or the last status
+pub fn exit(ctx: &mut Context, s: &[&String]) -> i32 {
+    if s.len() > 1 {
+        eprintln!("yui: exit: too many arguments");
+        return 1;
+    }
+    let status = match s.first() {
+        None => ctx.laststatus,
+        Some(n) => match n.parse::<i64>() {
+            Ok(n) => n.rem_euclid(256) as i32,
+            Err(_) => {
+                eprintln!("yui: exit: {}: numeric argument required", n);
+                2
+            }
+        },
+    };
+    ctx.exiting = Some(status);
+    status
+}
+
+pub fn jobs(ctx: &mut Context, s: &[&String]) -> i32 {
+    let (mut pids, mut only_pids) = (false, false);
+    for arg in s.iter() {
+        match arg.as_str() {
+            "-l" => pids = true,
+            "-p" => only_pids = true,
+            _ => {
+                eprintln!("yui: jobs: {}: invalid option\n  jobs [-l|-p]", arg);
+                return 2;
+            }
+        }
+    }
+    if only_pids {
+        for pid in ctx.jobs.leaders().iter() {
+            println!("{}", pid);
+        }
+    } else {
+        for line in ctx.jobs.list(pids).iter() {
+            println!("{}", line);
+        }
+    }
+    0
+}
+
+pub fn fg(ctx: &mut Context, s: &[&String]) -> i32 {
+    if !ctx.jobs.control {
+        eprintln!("yui: fg: no job control");
+        return 1;
+    }
+    match ctx.jobs.find(s.first().map(|a| a.as_str())) {
+        Ok(index) => {
+            let job = ctx.jobs.take(index);
+            println!("{}", job.cmd);
+            ctx.jobs.wait_fg(job, true)
+        }
+        Err(e) => {
+            eprintln!("yui: fg: {}", e);
+            1
+        }
+    }
+}
+
+pub fn bg(ctx: &mut Context, s: &[&String]) -> i32 {
+    if !ctx.jobs.control {
+        eprintln!("yui: bg: no job control");
+        return 1;
+    }
+    match ctx.jobs.find(s.first().map(|a| a.as_str())) {
+        Ok(index) => {
+            ctx.jobs.resume_bg(index);
+            0
+        }
+        Err(e) => {
+            eprintln!("yui: bg: {}", e);
+            1
+        }
+    }
+}
+
+// Wait for the given jobs or pids, or every job, returning the status of the last one
+pub fn wait(ctx: &mut Context, s: &[&String]) -> i32 {
     if s.is_empty() {
-        println!("History from file: {}\n", hist);
-        let reader = BufReader::new(File::open(hist).unwrap());
-        for (i, l) in reader.lines().enumerate() {
-            println!("{: >8}   {}", i, l.unwrap());
+        ctx.jobs.wait_all();
+        return 0;
+    }
+    let mut status = 0;
+    for arg in s.iter() {
+        let index = if arg.starts_with('%') {
+            ctx.jobs.find(Some(arg)).map_err(|e| format!("yui: wait: {}", e))
+        } else {
+            match arg.parse::<i32>() {
+                Ok(pid) => ctx.jobs.find_pid(pid).ok_or(format!("yui: wait: pid {} is not a child of this shell", pid)),
+                Err(_) => Err(format!("yui: wait: `{}': not a pid or valid job spec", arg)),
+            }
+        };
+        status = match index {
+            Ok(index) => ctx.jobs.wait_done(index),
+            Err(e) => {
+                eprintln!("{}", e);
+                127
+            }
+        };
+    }
+    status
+}
+
+// Forget about jobs, so the shell doesn't wait for them or report on them
+pub fn disown(ctx: &mut Context, s: &[&String]) -> i32 {
+    if s.first().map(|a| a.as_str()) == Some("-a") {
+        while let Ok(index) = ctx.jobs.find(None) {
+            ctx.jobs.take(index);
+        }
+        return 0;
+    }
+    let specs: Vec<Option<&str>> = if s.is_empty() { vec![None] } else { s.iter().map(|a| Some(a.as_str())).collect() };
+    let mut status = 0;
+    for spec in specs.into_iter() {
+        match ctx.jobs.find(spec) {
+            Ok(index) => {
+                ctx.jobs.take(index);
+            }
+            Err(e) => {
+                eprintln!("yui: disown: {}", e);
+                status = 1;
+            }
         }
-    } /* else if s.get(0) == "clear" {
-      }*/
+    }
+    status
+}
+
+// history [-l] [N], -c, -d offset[-offset], -a/-r/-w [file], -s args or search PATTERN. Entries
+// are numbered from 1 like history expansion, negative offsets count back from the end. -l shows
+// when, where and how each entry ran as well.
+pub fn history(ctx: &mut Context, s: &[&String]) -> i32 {
+    let long = s.first().map(|a| a.as_str()) == Some("-l");
+    let s = if long { &s[1..] } else { s };
+    let hist = &mut ctx.history;
+    match s.first().map(|a| a.as_str()) {
+        None => {
+            print_history(hist.records(), 0, long, |_| true);
+            0
+        }
+        Some("-c") => {
+            hist.clear();
+            0
+        }
+        Some("-d") => {
+            let arg = match s.get(1) {
+                Some(a) => a,
+                None => {
+                    eprintln!("yui: history: -d: option requires an argument");
+                    return 2;
+                }
+            };
+            // a dash after the first character separates the ends of a range
+            let (first, last) = match arg.char_indices().skip(1).find(|(_, c)| *c == '-') {
+                Some((i, _)) => (&arg[..i], &arg[i + 1..]),
+                None => (arg.as_str(), arg.as_str()),
+            };
+            let len = hist.records().len();
+            match (history_position(len, first), history_position(len, last)) {
+                (Some(first), Some(last)) if first <= last => {
+                    hist.remove(first, last);
+                    0
+                }
+                _ => {
+                    eprintln!("yui: history: {}: history position out of range", arg);
+                    1
+                }
+            }
+        }
+        Some("-a") | Some("-r") | Some("-w") => {
+            let file = s.get(1).map(|f| f.as_str()).unwrap_or(&ctx.histfile);
+            let result = match s[0].as_str() {
+                "-a" => hist.append(file),
+                "-r" => hist.read(file),
+                _ => hist.write(file),
+            };
+            match result {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("yui: history: {}: {}", file, e);
+                    1
+                }
+            }
+        }
+        Some("-s") => {
+            let entry: Vec<&str> = s[1..].iter().map(|a| a.as_str()).collect();
+            hist.add(&entry.join(" "));
+            0
+        }
+        Some("search") => {
+            let re = match s.get(1).map(|p| Regex::new(p)) {
+                Some(Ok(re)) => re,
+                Some(Err(_)) => {
+                    eprintln!("yui: history: search: {}: invalid pattern", s[1]);
+                    return 2;
+                }
+                None => {
+                    eprintln!("yui: history: search: a pattern is required");
+                    return 2;
+                }
+            };
+            // like grep, finding nothing is a failure
+            match print_history(hist.records(), 0, long, |l| re.is_match(l)) {
+                0 => 1,
+                _ => 0,
+            }
+        }
+        Some(opt) if opt.starts_with('-') && opt.len() > 1 => {
+            eprintln!("yui: history: {}: invalid option", opt);
+            eprintln!(
+                "history: usage: history [-l] [-c] [-d offset[-offset]] [n] [-a|-r|-w [file]] [-s arg...] [search pattern]"
+            );
+            2
+        }
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) => {
+                let records = hist.records();
+                print_history(records, records.len().saturating_sub(n), long, |_| true);
+                0
+            }
+            Err(_) => {
+                eprintln!("yui: history: {}: numeric argument required", n);
+                1
+            }
+        },
+    }
+}
+
+// Print the entries from index skip on that match, returning how many did
+fn print_history(records: &[Record], skip: usize, long: bool, matches: impl Fn(&str) -> bool) -> i32 {
+    let mut count = 0;
+    for (i, r) in records.iter().enumerate().skip(skip).filter(|(_, r)| matches(&r.command)) {
+        if long {
+            // anything not known, like for entries from an old history file, is shown as -
+            let or_dash = |s: Option<String>| s.filter(|s| !s.is_empty()).unwrap_or_else(|| "-".to_string());
+            println!(
+                "{: >8}   {: <19}  {: >9}  {: >3}  {}  {}  {}  {}",
+                i + 1,
+                or_dash(r.start.map(|s| sys::strftime(s as i64, "%Y-%m-%d %H:%M:%S"))),
+                or_dash(r.duration.map(|d| format!("{}.{:03}s", d / 1000, d % 1000))),
+                or_dash(r.status.map(|s| s.to_string())),
+                or_dash(Some(r.host.clone())),
+                or_dash(Some(r.session.clone())),
+                or_dash(Some(paths::condense_home(&r.cwd))),
+                r.command
+            );
+        } else {
+            println!("{: >8}   {}", i + 1, r.command);
+        }
+        count += 1;
+    }
+    count
+}
+
+// The index of history entry n, counting back from the end if n is negative
+fn history_position(len: usize, n: &str) -> Option<usize> {
+    let n: i64 = n.parse().ok()?;
+    let index = if n < 0 { len as i64 + n } else { n - 1 };
+    if index >= 0 && (index as usize) < len {
+        Some(index as usize)
+    } else {
+        None
+    }
 }
 
 #[cfg(test)]
@@ -132,8 +710,144 @@ mod tests {
     fn cd_basic_test() {
         let path = "/tmp".to_string();
         let vec = vec![&path];
-        cd(&vec);
+        cd(&Context::new(), &vec);
         let new = env::current_dir().expect("can't get current dir");
         assert_eq!("/tmp", new.as_os_str().to_str().unwrap());
     }
+
+    #[test]
+    fn test_variables() {
+        let mut ctx = Context::new();
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        ctx.set_var("YUI_TEST_SHELL", "1").unwrap();
+        assert_eq!(ctx.get_var("YUI_TEST_SHELL"), Some("1".to_string()));
+        assert!(!ctx.environment().iter().any(|(n, _)| n == "YUI_TEST_SHELL"));
+
+        let a = args(&["YUI_TEST_SHELL", "YUI_TEST_NEW=x", "YUI_TEST_EMPTY", "1bad"]);
+        assert_eq!(export(&mut ctx, &a.iter().collect::<Vec<&String>>()), 1);
+        let env = ctx.environment();
+        assert!(env.contains(&("YUI_TEST_SHELL".to_string(), "1".to_string())));
+        assert!(env.contains(&("YUI_TEST_NEW".to_string(), "x".to_string())));
+        // exported but without a value, so not passed on yet
+        assert!(!env.iter().any(|(n, _)| n == "YUI_TEST_EMPTY"));
+        assert_eq!(ctx.get_var("YUI_TEST_EMPTY"), None);
+
+        let a = args(&["YUI_TEST_RO=v"]);
+        assert_eq!(readonly(&mut ctx, &a.iter().collect::<Vec<&String>>()), 0);
+        assert!(ctx.set_var("YUI_TEST_RO", "w").is_err());
+        let a = args(&["YUI_TEST_RO", "YUI_TEST_NEW"]);
+        assert_eq!(unset(&mut ctx, &a.iter().collect::<Vec<&String>>()), 1);
+        assert_eq!(ctx.get_var("YUI_TEST_RO"), Some("v".to_string()));
+        assert_eq!(ctx.get_var("YUI_TEST_NEW"), None);
+
+        assert_eq!(quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_test() {
+        let t = |a: &str| {
+            let args: Vec<String> = a.split(' ').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect();
+            test(&args.iter().collect::<Vec<&String>>(), false)
+        };
+        assert_eq!(t("abc = abc"), 0);
+        assert_eq!(t("abc != abc"), 1);
+        assert_eq!(t("a < b"), 0);
+        assert_eq!(t("-5 -lt 3"), 0);
+        assert_eq!(t("10 -ge 11"), 1);
+        assert_eq!(t("x -eq 1"), 2);
+        assert_eq!(t("-n x -a -z x"), 1);
+        assert_eq!(t("-n x -o -z x"), 0);
+        assert_eq!(t("! -n x"), 1);
+        assert_eq!(t("( 1 -eq 2 -o 2 -eq 2 ) -a ! ( a = b )"), 0);
+        assert_eq!(t("( a = a"), 2);
+        assert_eq!(t(""), 1);
+        assert_eq!(t("-n"), 0);
+        assert_eq!(t("! = !"), 0);
+        assert_eq!(t("-d /"), 0);
+        assert_eq!(t("-f /"), 1);
+        assert_eq!(t("-e /nonexistent/yui"), 1);
+
+        let a: Vec<String> = ["1", "-eq", "1"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(test(&a.iter().collect::<Vec<&String>>(), true), 2);
+        let a: Vec<String> = ["1", "-eq", "1", "]"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(test(&a.iter().collect::<Vec<&String>>(), true), 0);
+    }
+
+    #[test]
+    fn test_local_and_return() {
+        let mut ctx = Context::new();
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let a = args(&["YUI_TEST_LOCAL=in"]);
+        assert_eq!(local(&mut ctx, &a.iter().collect::<Vec<&String>>()), 1);
+        assert_eq!(return_(&mut ctx, &[]), 1);
+        assert_eq!(ctx.returning, None);
+
+        ctx.set_var("YUI_TEST_LOCAL", "out").unwrap();
+        ctx.frames.push(crate::context::Frame::default());
+        assert_eq!(local(&mut ctx, &a.iter().collect::<Vec<&String>>()), 0);
+        assert_eq!(ctx.get_var("YUI_TEST_LOCAL"), Some("in".to_string()));
+        assert_eq!(ctx.frames[0].saved["YUI_TEST_LOCAL"].as_ref().unwrap().value, Some("out".to_string()));
+
+        let a = args(&["300"]);
+        assert_eq!(return_(&mut ctx, &a.iter().collect::<Vec<&String>>()), 44);
+        assert_eq!(ctx.returning, Some(44));
+        assert!(ctx.stopping());
+    }
+
+    #[test]
+    fn test_history() {
+        let mut ctx = Context::new();
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let run = |ctx: &mut Context, a: &[&str]| history(ctx, &args(a).iter().collect::<Vec<&String>>());
+        let entries = |ctx: &Context| ctx.history.records().iter().map(|r| r.command.clone()).collect::<Vec<String>>();
+        for entry in ["one", "two", "three", "four", "five"].iter() {
+            assert_eq!(run(&mut ctx, &["-s", entry]), 0);
+        }
+        assert_eq!(run(&mut ctx, &["-d", "2"]), 0);
+        assert_eq!(entries(&ctx), vec!["one", "three", "four", "five"]);
+        assert_eq!(run(&mut ctx, &["-d", "-2--1"]), 0);
+        assert_eq!(entries(&ctx), vec!["one", "three"]);
+        assert_eq!(run(&mut ctx, &["-d", "3"]), 1);
+        assert_eq!(run(&mut ctx, &["-d", "2-1"]), 1);
+        assert_eq!(run(&mut ctx, &["-d"]), 2);
+
+        assert_eq!(run(&mut ctx, &["search", "^th"]), 0);
+        assert_eq!(run(&mut ctx, &["search", "nothing"]), 1);
+        assert_eq!(run(&mut ctx, &["search", "("]), 2);
+        assert_eq!(run(&mut ctx, &[]), 0);
+        assert_eq!(run(&mut ctx, &["1"]), 0);
+        assert_eq!(run(&mut ctx, &["-l", "1"]), 0);
+        assert_eq!(run(&mut ctx, &["-l", "search", "one"]), 0);
+        assert_eq!(run(&mut ctx, &["x"]), 1);
+        assert_eq!(run(&mut ctx, &["-x"]), 2);
+
+        let file = env::temp_dir().join(format!("yui-history-test-{}", std::process::id()));
+        let file = file.to_str().unwrap();
+        assert_eq!(run(&mut ctx, &["-w", file]), 0);
+        assert_eq!(run(&mut ctx, &["-c"]), 0);
+        assert!(entries(&ctx).is_empty());
+        assert_eq!(run(&mut ctx, &["-r", file]), 0);
+        assert_eq!(entries(&ctx), vec!["one", "three"]);
+        std::fs::remove_file(file).unwrap();
+        assert_eq!(run(&mut ctx, &["-r", file]), 1);
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut ctx = Context::new();
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        ctx.laststatus = 4;
+        assert_eq!(exit(&mut ctx, &[]), 4);
+        assert_eq!(ctx.exiting, Some(4));
+        for (a, status) in [("3", 3), ("256", 0), ("-1", 255), ("nope", 2)].iter() {
+            let a = args(&[a]);
+            assert_eq!(exit(&mut ctx, &a.iter().collect::<Vec<&String>>()), *status);
+            assert_eq!(ctx.exiting, Some(*status));
+        }
+
+        ctx.exiting = None;
+        let a = args(&["1", "2"]);
+        assert_eq!(exit(&mut ctx, &a.iter().collect::<Vec<&String>>()), 1);
+        assert_eq!(ctx.exiting, None);
+    }
 }
@@ -32,6 +32,26 @@ pub struct Config {
     pub indent_size: usize,
     pub bracketed_paste: bool,
     pub prompt_string: String,
+    pub glob_nomatch: NoMatch,
+    pub float_math: bool,  // arithmetic on floats instead of integers
+    pub magic_space: bool, // expand the word before the cursor when space is pressed
+    pub hist_share: HistShare,
+}
+
+// What to do with a glob that doesn't match any files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoMatch {
+    Literal, // pass the pattern through unchanged
+    Error,   // refuse to run the command
+    Null,    // remove the word
+}
+
+// When to pick up the commands other shells have added to the history file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HistShare {
+    Off,
+    Key,    // when alt-r is pressed
+    Prompt, // before every prompt, and with alt-r
 }
 
 // Define defaults here
@@ -53,11 +73,15 @@ impl Default for Config {
             indent_size: 2,
             bracketed_paste: true,
             prompt_string: ">> ".to_string(),
+            glob_nomatch: NoMatch::Literal,
+            float_math: false,
+            magic_space: false,
+            hist_share: HistShare::Key,
         }
     }
 }
 
-pub fn aliasblock_parse_and_exec(ctx: &mut Context, aliasline: &String) -> bool {
+pub fn aliasblock_parse_and_exec(ctx: &mut Context, aliasline: &str) -> bool {
     if CHECK_EQ.is_match(aliasline) {
         for mat in CHECK_EQ.captures_iter(aliasline) {
             let alias = mat[1].to_string();
@@ -75,35 +99,39 @@ pub fn aliasblock_parse_and_exec(ctx: &mut Context, aliasline: &String) -> bool
     }
 }
 
-pub fn setblock_parse_and_exec(ctx: &mut Context, setline: &String) -> bool {
+pub fn setblock_parse_and_exec(ctx: &mut Context, setline: &str) -> bool {
     let mut split = setline.split("=");
     let key = split.next().unwrap();
     let raw = split.next().unwrap();
-    convert_and_set_key(ctx, &key, &raw)
+    convert_and_set_key(ctx, key, raw)
 }
 
 pub fn convert_and_set_key(ctx: &mut Context, key: &str, raw: &str) -> bool {
     // TODO: find cleaner way to do this?
     match key {
-        "hist_ign_space" => ctx.config.hist_ign_space = string_to_type(raw, &"boolean").into(),
-        "hist_ign_dups" => ctx.config.hist_ign_dups = string_to_type(raw, &"boolean").into(),
-        "hist_max_size" => ctx.config.hist_max_size = string_to_type(raw, &"size").into(),
-        "completion_type" => ctx.config.completion_type = string_to_type(raw, &"complete").into(),
-        "completion_limit" => ctx.config.completion_limit = string_to_type(raw, &"size").into(),
-        "keyseq_timeout" => ctx.config.keyseq_timeout = string_to_type(raw, &"int32").into(),
-        "edit_mode" => ctx.config.edit_mode = string_to_type(raw, &"edit").into(),
-        "auto_add_history" => ctx.config.auto_add_history = string_to_type(raw, &"boolean").into(),
-        "bell_style" => ctx.config.bell_style = string_to_type(raw, &"bell").into(),
-        "color_mode" => ctx.config.color_mode = string_to_type(raw, &"color").into(),
-        "tab_stop" => ctx.config.tab_stop = string_to_type(raw, &"size").into(),
-        "check_cur_pos" => ctx.config.check_cur_pos = string_to_type(raw, &"boolean").into(),
-        "indent_size" => ctx.config.indent_size = string_to_type(raw, &"size").into(),
-        "bracketed_paste" => ctx.config.bracketed_paste = string_to_type(raw, &"boolean").into(),
+        "hist_ign_space" => ctx.config.hist_ign_space = string_to_type(raw, "boolean").into(),
+        "hist_ign_dups" => ctx.config.hist_ign_dups = string_to_type(raw, "boolean").into(),
+        "hist_max_size" => ctx.config.hist_max_size = string_to_type(raw, "size").into(),
+        "completion_type" => ctx.config.completion_type = string_to_type(raw, "complete").into(),
+        "completion_limit" => ctx.config.completion_limit = string_to_type(raw, "size").into(),
+        "keyseq_timeout" => ctx.config.keyseq_timeout = string_to_type(raw, "int32").into(),
+        "edit_mode" => ctx.config.edit_mode = string_to_type(raw, "edit").into(),
+        "auto_add_history" => ctx.config.auto_add_history = string_to_type(raw, "boolean").into(),
+        "bell_style" => ctx.config.bell_style = string_to_type(raw, "bell").into(),
+        "color_mode" => ctx.config.color_mode = string_to_type(raw, "color").into(),
+        "tab_stop" => ctx.config.tab_stop = string_to_type(raw, "size").into(),
+        "check_cur_pos" => ctx.config.check_cur_pos = string_to_type(raw, "boolean").into(),
+        "indent_size" => ctx.config.indent_size = string_to_type(raw, "size").into(),
+        "bracketed_paste" => ctx.config.bracketed_paste = string_to_type(raw, "boolean").into(),
         "hinting_color" => {
             let mut color = HINT_COLOR.lock().unwrap();
-            *color = string_to_type(raw, &"colorname").into();
+            *color = string_to_type(raw, "colorname").into();
         }
         "prompt" => ctx.config.prompt_string = raw.to_string(),
+        "glob_nomatch" => ctx.config.glob_nomatch = string_to_type(raw, "nomatch").into(),
+        "float_math" => ctx.config.float_math = string_to_type(raw, "boolean").into(),
+        "magic_space" => ctx.config.magic_space = string_to_type(raw, "boolean").into(),
+        "hist_share" => ctx.config.hist_share = string_to_type(raw, "share").into(),
         _ => return false,
     }
     true
@@ -119,13 +147,15 @@ enum ConfigTypes {
     EditMode(EditMode),
     BellStyle(BellStyle),
     Color(ColorMode),
+    NoMatch(NoMatch),
+    HistShare(HistShare),
     Error(), // For if the value in the config is invalid
 }
 
 // TODO: write a proc macro for this, will be helpful if we add more settings options
-impl Into<bool> for ConfigTypes {
-    fn into(self) -> bool {
-        if let ConfigTypes::Boolean(b) = self {
+impl From<ConfigTypes> for bool {
+    fn from(val: ConfigTypes) -> Self {
+        if let ConfigTypes::Boolean(b) = val {
             b
         } else {
             eprintln!("set: unsupported value");
@@ -133,9 +163,9 @@ impl Into<bool> for ConfigTypes {
         }
     }
 }
-impl Into<usize> for ConfigTypes {
-    fn into(self) -> usize {
-        if let ConfigTypes::Size(s) = self {
+impl From<ConfigTypes> for usize {
+    fn from(val: ConfigTypes) -> Self {
+        if let ConfigTypes::Size(s) = val {
             s
         } else {
             eprintln!("set: unsupported value");
@@ -143,9 +173,9 @@ impl Into<usize> for ConfigTypes {
         }
     }
 }
-impl Into<i32> for ConfigTypes {
-    fn into(self) -> i32 {
-        if let ConfigTypes::Num(s) = self {
+impl From<ConfigTypes> for i32 {
+    fn from(val: ConfigTypes) -> Self {
+        if let ConfigTypes::Num(s) = val {
             s
         } else {
             eprintln!("set: unsupported value");
@@ -153,9 +183,9 @@ impl Into<i32> for ConfigTypes {
         }
     }
 }
-impl Into<CompletionType> for ConfigTypes {
-    fn into(self) -> CompletionType {
-        if let ConfigTypes::Completion(c) = self {
+impl From<ConfigTypes> for CompletionType {
+    fn from(val: ConfigTypes) -> Self {
+        if let ConfigTypes::Completion(c) = val {
             c
         } else {
             eprintln!("set: unsupported value");
@@ -163,9 +193,9 @@ impl Into<CompletionType> for ConfigTypes {
         }
     }
 }
-impl Into<Color> for ConfigTypes {
-    fn into(self) -> Color {
-        if let ConfigTypes::ColorNames(c) = self {
+impl From<ConfigTypes> for Color {
+    fn from(val: ConfigTypes) -> Self {
+        if let ConfigTypes::ColorNames(c) = val {
             c
         } else {
             eprintln!("set: unsupported value");
@@ -173,9 +203,9 @@ impl Into<Color> for ConfigTypes {
         }
     }
 }
-impl Into<EditMode> for ConfigTypes {
-    fn into(self) -> EditMode {
-        if let ConfigTypes::EditMode(m) = self {
+impl From<ConfigTypes> for EditMode {
+    fn from(val: ConfigTypes) -> Self {
+        if let ConfigTypes::EditMode(m) = val {
             m
         } else {
             eprintln!("set: unsupported value");
@@ -183,9 +213,9 @@ impl Into<EditMode> for ConfigTypes {
         }
     }
 }
-impl Into<BellStyle> for ConfigTypes {
-    fn into(self) -> BellStyle {
-        if let ConfigTypes::BellStyle(b) = self {
+impl From<ConfigTypes> for BellStyle {
+    fn from(val: ConfigTypes) -> Self {
+        if let ConfigTypes::BellStyle(b) = val {
             b
         } else {
             eprintln!("set: unsupported value");
@@ -193,9 +223,9 @@ impl Into<BellStyle> for ConfigTypes {
         }
     }
 }
-impl Into<ColorMode> for ConfigTypes {
-    fn into(self) -> ColorMode {
-        if let ConfigTypes::Color(c) = self {
+impl From<ConfigTypes> for ColorMode {
+    fn from(val: ConfigTypes) -> Self {
+        if let ConfigTypes::Color(c) = val {
             c
         } else {
             eprintln!("set: unsupported value");
@@ -203,6 +233,26 @@ impl Into<ColorMode> for ConfigTypes {
         }
     }
 }
+impl From<ConfigTypes> for NoMatch {
+    fn from(val: ConfigTypes) -> Self {
+        if let ConfigTypes::NoMatch(n) = val {
+            n
+        } else {
+            eprintln!("set: unsupported value");
+            exit(1)
+        }
+    }
+}
+impl From<ConfigTypes> for HistShare {
+    fn from(val: ConfigTypes) -> Self {
+        if let ConfigTypes::HistShare(h) = val {
+            h
+        } else {
+            eprintln!("set: unsupported value");
+            exit(1)
+        }
+    }
+}
 
 // We have to convert strings from the config to proper formats.. Pain..
 fn string_to_type(string: &str, target: &str) -> ConfigTypes {
@@ -278,6 +328,18 @@ fn string_to_type(string: &str, target: &str) -> ConfigTypes {
                 ConfigTypes::Error()
             }
         }
+        "nomatch" => match matcher.as_str() {
+            "literal" => ConfigTypes::NoMatch(NoMatch::Literal),
+            "error" => ConfigTypes::NoMatch(NoMatch::Error),
+            "null" => ConfigTypes::NoMatch(NoMatch::Null),
+            _ => ConfigTypes::Error(),
+        },
+        "share" => match matcher.as_str() {
+            "off" => ConfigTypes::HistShare(HistShare::Off),
+            "key" => ConfigTypes::HistShare(HistShare::Key),
+            "prompt" => ConfigTypes::HistShare(HistShare::Prompt),
+            _ => ConfigTypes::Error(),
+        },
         _ => ConfigTypes::Error(),
     }
 }
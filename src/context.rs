@@ -1,14 +1,42 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, env, process, rc::Rc};
 
+use crate::ast::FunctionDef;
 use crate::config::Config;
+use crate::histfile::Store;
+use crate::jobs::JobTable;
 use crate::paths;
 
-#[derive(Clone)]
+// A shell variable. Only exported ones are passed on to commands, and a variable can be exported
+// or readonly before it has a value.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Var {
+    pub value: Option<String>,
+    pub exported: bool,
+    pub readonly: bool,
+}
+
 pub struct Context {
     pub config: Config,
     pub histfile: String,
     pub aliases: HashMap<String, String>,
-    pub laststatus: i32, // exit status of last command
+    pub vars: HashMap<String, Var>, // shell variables, starting with the environment yui was run in
+    pub laststatus: i32,            // exit status of last command
+    pub arg0: String,               // $0, the shell or script name
+    pub args: Vec<String>,          // positional parameters, $1 and up
+    pub pid: u32,                   // $$, stays the same in forked children
+    pub last_bg: Option<i32>,       // $!, pid of the last background job
+    pub jobs: JobTable,
+    pub exiting: Option<i32>, // set by the exit builtin, the status to exit with
+    pub functions: HashMap<String, Rc<FunctionDef>>,
+    pub frames: Vec<Frame>,     // the functions being run, innermost last
+    pub returning: Option<i32>, // set by the return builtin, the status to return with
+    pub history: Store,         // lines entered at the prompt
+}
+
+// A function call in progress, with what its local variables were before it
+#[derive(Clone, Default)]
+pub struct Frame {
+    pub saved: HashMap<String, Option<Var>>,
 }
 
 impl Context {
@@ -17,8 +45,99 @@ impl Context {
             config: Config::default(),
             histfile: [paths::get_user_home(), ".yui_history".to_string()].join("/"),
             aliases: HashMap::new(),
+            vars: env::vars_os()
+                .filter_map(|(n, v)| Some((n.into_string().ok()?, v.into_string().ok()?)))
+                .map(|(n, v)| (n, Var { value: Some(v), exported: true, readonly: false }))
+                .collect(),
             laststatus: 0,
+            arg0: env::args().next().unwrap_or_else(|| "yui".to_string()),
+            args: Vec::new(),
+            pid: process::id(),
+            last_bg: None,
+            jobs: JobTable::default(),
+            exiting: None,
+            functions: HashMap::new(),
+            frames: Vec::new(),
+            returning: None,
+            history: Store::default(),
+        }
+    }
+
+    // exit or return is unwinding the commands being run
+    pub fn stopping(&self) -> bool {
+        self.exiting.is_some() || self.returning.is_some()
+    }
+
+    // Make name local to the innermost function, it is restored when the function returns
+    pub fn make_local(&mut self, name: &str) -> Result<(), String> {
+        if self.is_readonly(name) {
+            return Err(format!("{}: readonly variable", name));
         }
+        let frame = self.frames.last_mut().ok_or_else(|| "can only be used in a function".to_string())?;
+        if !frame.saved.contains_key(name) {
+            frame.saved.insert(name.to_string(), self.vars.remove(name));
+            self.vars.insert(name.to_string(), Var::default());
+        }
+        Ok(())
+    }
+
+    // Special parameters like $? and $1, None if name isn't one
+    pub fn get_special(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.laststatus.to_string()),
+            "$" => Some(self.pid.to_string()),
+            "!" => Some(self.last_bg.map(|p| p.to_string()).unwrap_or_default()),
+            "#" => Some(self.args.len().to_string()),
+            "@" | "*" => Some(self.args.join(" ")),
+            "0" => Some(self.arg0.clone()),
+            _ => match name.parse::<usize>() {
+                Ok(n) if n > 0 => Some(self.args.get(n - 1).cloned().unwrap_or_default()),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        self.vars.get(name).and_then(|v| v.value.clone())
+    }
+
+    // Errors are ready to print after "yui: "
+    pub fn set_var(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let var = self.vars.entry(name.to_string()).or_default();
+        if var.readonly {
+            return Err(format!("{}: readonly variable", name));
+        }
+        var.value = Some(value.to_string());
+        Ok(())
+    }
+
+    pub fn unset_var(&mut self, name: &str) -> Result<(), String> {
+        if self.vars.get(name).filter(|v| v.readonly).is_some() {
+            return Err(format!("{}: cannot unset: readonly variable", name));
+        }
+        self.vars.remove(name);
+        Ok(())
+    }
+
+    pub fn is_readonly(&self, name: &str) -> bool {
+        self.vars.get(name).filter(|v| v.readonly).is_some()
+    }
+
+    // The exported variables with a value, what commands are run with
+    pub fn environment(&self) -> Vec<(String, String)> {
+        let mut env: Vec<(String, String)> = self
+            .vars
+            .iter()
+            .filter(|(_, v)| v.exported)
+            .filter_map(|(n, v)| Some((n.clone(), v.value.clone()?)))
+            .collect();
+        env.sort();
+        env
+    }
+
+    // $HOME, or the user's home directory if it isn't set
+    pub fn home(&self) -> String {
+        self.get_var("HOME").unwrap_or_else(paths::get_user_home)
     }
 
     pub fn new_alias(&mut self, alias: String, value: String) {
@@ -0,0 +1,792 @@
+use std::fmt;
+
+use crate::arith;
+use crate::config::NoMatch;
+use crate::context::Context;
+use crate::glob;
+use crate::parser;
+use crate::spawn;
+
+// An expansion that can't be done, eg. ${VAR:?msg} with VAR unset. The message is ready to print.
+#[derive(Debug, PartialEq)]
+pub struct ExpandError(pub String);
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Expand every raw word of a command, unquoted expansions are split into separate words
+// and unquoted glob patterns are replaced by the files they match
+pub fn expand_words(ctx: &mut Context, words: &[String]) -> Result<Vec<String>, ExpandError> {
+    let mut ex = Expander::new(ctx, true);
+    for word in words.iter() {
+        for w in brace_expand(word).iter() {
+            ex.word(w)?;
+            ex.finish_field();
+        }
+    }
+
+    let nomatch = ex.ctx.config.glob_nomatch;
+    let mut out = Vec::new();
+    for field in ex.fields.into_iter() {
+        let pattern = match field.pattern {
+            Some(p) => p,
+            None => {
+                out.push(field.text);
+                continue;
+            }
+        };
+        let found = glob::expand(&pattern);
+        if !found.is_empty() {
+            out.extend(found);
+            continue;
+        }
+        match nomatch {
+            NoMatch::Literal => out.push(field.text),
+            NoMatch::Error => return Err(ExpandError(format!("no matches found: {}", field.text))),
+            NoMatch::Null => {}
+        }
+    }
+    Ok(out)
+}
+
+// Expand a single word without splitting it, for redirection targets and assignments
+pub fn expand_word(ctx: &mut Context, word: &str) -> Result<String, ExpandError> {
+    let mut ex = Expander::new(ctx, false);
+    ex.word(word)?;
+    Ok(ex.cur)
+}
+
+// Expand the value of an assignment, where ~ also expands after each unquoted ':', eg. PATH=~/bin:~/.local/bin
+pub fn expand_assignment(ctx: &mut Context, value: &str) -> Result<String, ExpandError> {
+    let mut ex = Expander::new(ctx, false);
+    ex.assign = true;
+    ex.word(value)?;
+    Ok(ex.cur)
+}
+
+// Expand and evaluate the inside of $((...)) or ((...))
+pub fn arithmetic(ctx: &mut Context, expr: &str) -> Result<arith::Num, ExpandError> {
+    let mut ex = Expander::new(ctx, false);
+    ex.scan(&expr.chars().collect::<Vec<char>>(), Mode::Unquoted)?;
+    let text = ex.cur;
+    arith::eval(ctx, &text).map_err(|e| ExpandError(format!("{}: {}", text.trim(), e)))
+}
+
+// Heredoc bodies expand parameters but leave quotes alone
+pub fn expand_heredoc(ctx: &mut Context, body: &str) -> Result<String, ExpandError> {
+    let mut ex = Expander::new(ctx, false);
+    ex.scan(&body.chars().collect::<Vec<char>>(), Mode::HereDoc)?;
+    Ok(ex.cur)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Unquoted,
+    DoubleQuoted,
+    HereDoc,
+}
+
+// A finished word, with the glob pattern to match if it had unquoted glob characters
+struct Field {
+    text: String,
+    pattern: Option<String>,
+}
+
+struct Expander<'a> {
+    ctx: &'a mut Context,
+    split: bool,        // split unquoted expansions on whitespace
+    fields: Vec<Field>, // finished words
+    cur: String,        // the word being built
+    pattern: String,    // cur with quoted glob characters escaped
+    glob: bool,         // cur has unquoted glob characters
+    have_cur: bool,     // cur is a word even if it's empty, eg. from ""
+    assign: bool,       // expanding an assignment value, ~ also expands after ':'
+}
+
+impl<'a> Expander<'a> {
+    fn new(ctx: &'a mut Context, split: bool) -> Self {
+        Self {
+            ctx,
+            split,
+            fields: Vec::new(),
+            cur: String::new(),
+            pattern: String::new(),
+            glob: false,
+            have_cur: false,
+            assign: false,
+        }
+    }
+
+    fn word(&mut self, word: &str) -> Result<(), ExpandError> {
+        let chars: Vec<char> = word.chars().collect();
+        let start = self.tilde(&chars);
+        self.scan(&chars[start..], Mode::Unquoted)
+    }
+
+    // Expand a ~ at the start of chars, returning how many characters it took
+    fn tilde(&mut self, chars: &[char]) -> usize {
+        let end = |c: char| c == '/' || (self.assign && c == ':');
+        if chars.first() == Some(&'~') && (chars.len() == 1 || end(chars[1])) {
+            self.push(&self.ctx.home(), true);
+            return 1;
+        }
+        0
+    }
+
+    fn finish_field(&mut self) {
+        if self.have_cur {
+            let text = std::mem::take(&mut self.cur);
+            let pattern = std::mem::take(&mut self.pattern);
+            self.fields.push(Field { text, pattern: if self.glob { Some(pattern) } else { None } });
+            self.glob = false;
+            self.have_cur = false;
+        }
+    }
+
+    // quoted text never acts as a glob pattern
+    fn push(&mut self, s: &str, quoted: bool) {
+        self.cur.push_str(s);
+        for c in s.chars() {
+            if quoted && (glob::is_meta(c) || c == '\\') {
+                self.pattern.push('\\');
+            } else if glob::is_meta(c) {
+                self.glob = true;
+            }
+            self.pattern.push(c);
+        }
+        self.have_cur = true;
+    }
+
+    // the result of an expansion, which is split into words when unquoted
+    fn push_expanded(&mut self, s: &str, quoted: bool) {
+        if quoted || !self.split {
+            self.push(s, quoted);
+            return;
+        }
+        for (i, part) in s.split([' ', '\t', '\n']).enumerate() {
+            if i > 0 {
+                self.finish_field();
+            }
+            if !part.is_empty() {
+                self.push(part, false);
+            }
+        }
+    }
+
+    fn scan(&mut self, chars: &[char], mode: Mode) -> Result<(), ExpandError> {
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '\'' if mode == Mode::Unquoted => {
+                    let end = find_char(chars, i + 1, '\'');
+                    let text: String = chars[i + 1..end].iter().collect();
+                    self.push(&text, true);
+                    i = end + 1;
+                }
+                '"' if mode == Mode::Unquoted => {
+                    let end = find_dquote_end(chars, i + 1);
+                    let inner: String = chars[i + 1..end].iter().collect();
+                    if (inner == "$@" || inner == "${@}") && self.ctx.args.is_empty() {
+                        // "$@" without any arguments is no word at all, not an empty one
+                        i = end + 1;
+                        continue;
+                    }
+                    self.have_cur = true;
+                    self.scan(&chars[i + 1..end], Mode::DoubleQuoted)?;
+                    i = end + 1;
+                }
+                '\\' if i + 1 < chars.len() && escapes(mode, chars[i + 1]) => {
+                    if chars[i + 1] != '\n' {
+                        self.push(chars[i + 1].encode_utf8(&mut [0; 4]), true);
+                    }
+                    i += 2;
+                }
+                '$' => i = self.dollar(chars, i, mode != Mode::Unquoted)?,
+                '`' => {
+                    let end = find_backtick_end(chars, i + 1);
+                    // inside backticks a backslash only escapes $, ` and \
+                    let mut cmd = String::new();
+                    let mut j = i + 1;
+                    while j < end {
+                        if chars[j] == '\\' && j + 1 < end && matches!(chars[j + 1], '$' | '`' | '\\') {
+                            j += 1;
+                        }
+                        cmd.push(chars[j]);
+                        j += 1;
+                    }
+                    self.substitute(&cmd, mode != Mode::Unquoted)?;
+                    i = end + 1;
+                }
+                ':' if mode == Mode::Unquoted && self.assign => {
+                    self.push(":", false);
+                    i += 1 + self.tilde(&chars[i + 1..]);
+                }
+                // only possible in the default word of ${VAR:-a b}
+                ' ' | '\t' | '\n' if mode == Mode::Unquoted && self.split => {
+                    self.finish_field();
+                    i += 1;
+                }
+                c => {
+                    self.push(c.encode_utf8(&mut [0; 4]), mode != Mode::Unquoted);
+                    i += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Expand the parameter starting at chars[i], which is a '$'. Returns where to continue.
+    fn dollar(&mut self, chars: &[char], i: usize, quoted: bool) -> Result<usize, ExpandError> {
+        match chars.get(i + 1) {
+            Some('{') => {
+                let end = find_brace_end(chars, i + 2);
+                self.braced(&chars[i + 2..end], quoted)?;
+                Ok(end + 1)
+            }
+            // $((...)) when the inner parens close right before the outer one
+            Some('(')
+                if chars.get(i + 2) == Some(&'(')
+                    && find_paren_end(chars, i + 3) + 1 == find_paren_end(chars, i + 2) =>
+            {
+                let end = find_paren_end(chars, i + 2);
+                let expr: String = chars[i + 3..end - 1].iter().collect();
+                let n = arithmetic(self.ctx, &expr)?;
+                self.push_expanded(&n.to_string(), quoted);
+                Ok(end + 1)
+            }
+            Some('(') => {
+                let end = find_paren_end(chars, i + 2);
+                let cmd: String = chars[i + 2..end].iter().collect();
+                self.substitute(&cmd, quoted)?;
+                Ok(end + 1)
+            }
+            Some(c) if is_name_start(*c) => {
+                let end = name_end(chars, i + 1);
+                let name: String = chars[i + 1..end].iter().collect();
+                let value = self.param(&name).unwrap_or_default();
+                self.push_expanded(&value, quoted);
+                Ok(end)
+            }
+            // only a single digit, $10 is $1 followed by a 0
+            Some(c) if is_special(*c) || c.is_ascii_digit() => {
+                self.push_param(&c.to_string(), quoted);
+                Ok(i + 2)
+            }
+            _ => {
+                self.push("$", true);
+                Ok(i + 1)
+            }
+        }
+    }
+
+    // Run cmd and use what it printed, minus the trailing newlines
+    fn substitute(&mut self, cmd: &str, quoted: bool) -> Result<(), ExpandError> {
+        let list = parser::parse(cmd, None).map_err(|e| ExpandError(e.to_string()))?;
+        let (out, status) = spawn::capture(self.ctx, &list).map_err(ExpandError)?;
+        self.ctx.laststatus = status;
+        self.push_expanded(out.trim_end_matches('\n'), quoted);
+        Ok(())
+    }
+
+    fn param(&self, name: &str) -> Option<String> {
+        self.ctx.get_special(name).or_else(|| self.ctx.get_var(name))
+    }
+
+    // "$@" gives every argument as its own word, everything else is a normal expansion
+    fn push_param(&mut self, name: &str, quoted: bool) {
+        if name == "@" && quoted && self.split {
+            let args = self.ctx.args.clone();
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    self.finish_field();
+                }
+                self.push(arg, true);
+            }
+        } else {
+            let value = self.param(name).unwrap_or_default();
+            self.push_expanded(&value, quoted);
+        }
+    }
+
+    // The inside of ${...}
+    fn braced(&mut self, inner: &[char], quoted: bool) -> Result<(), ExpandError> {
+        let text: String = inner.iter().collect();
+        let bad = || ExpandError(format!("${{{}}}: bad substitution", text));
+
+        if inner.len() > 1 && inner[0] == '#' {
+            let name: String = inner[1..].iter().collect();
+            if param_end(&inner[1..]) != inner.len() - 1 {
+                return Err(bad());
+            }
+            let len = self.param(&name).map_or(0, |v| v.chars().count());
+            self.push_expanded(&len.to_string(), quoted);
+            return Ok(());
+        }
+
+        let end = param_end(inner);
+        if end == 0 {
+            return Err(bad());
+        }
+        let name: String = inner[..end].iter().collect();
+        let rest = &inner[end..];
+        if rest.is_empty() {
+            self.push_param(&name, quoted);
+            return Ok(());
+        }
+        let value = self.param(&name).filter(|v| !v.is_empty());
+        if rest.len() < 2 || rest[0] != ':' {
+            return Err(bad());
+        }
+        let word = &rest[2..];
+        let mode = if quoted { Mode::DoubleQuoted } else { Mode::Unquoted };
+        match (rest[1], value) {
+            ('-', Some(v)) | ('=', Some(v)) | ('?', Some(v)) => self.push_expanded(&v, quoted),
+            ('-', None) => self.scan(word, mode)?,
+            ('=', None) => {
+                let mut sub = Expander::new(self.ctx, false);
+                sub.scan(word, Mode::Unquoted)?;
+                let v = sub.cur;
+                if !is_name(&name) {
+                    return Err(ExpandError(format!("${}: cannot assign in this way", name)));
+                }
+                self.ctx.set_var(&name, &v).map_err(ExpandError)?;
+                self.push_expanded(&v, quoted);
+            }
+            ('?', None) => {
+                let mut sub = Expander::new(self.ctx, false);
+                sub.scan(word, Mode::Unquoted)?;
+                let msg = if sub.cur.is_empty() { "parameter null or not set".to_string() } else { sub.cur };
+                return Err(ExpandError(format!("{}: {}", name, msg)));
+            }
+            _ => return Err(bad()),
+        }
+        Ok(())
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if is_name_start(c)) && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Outside quotes a backslash escapes anything, elsewhere only the characters that would mean
+// something there. Any other backslash is kept as it is.
+fn escapes(mode: Mode, c: char) -> bool {
+    match mode {
+        Mode::Unquoted => true,
+        Mode::DoubleQuoted => matches!(c, '$' | '`' | '"' | '\\' | '\n'),
+        Mode::HereDoc => matches!(c, '$' | '`' | '\\' | '\n'),
+    }
+}
+
+fn is_special(c: char) -> bool {
+    matches!(c, '?' | '$' | '!' | '#' | '@' | '*')
+}
+
+// length of the parameter name at the start of ${...}, 0 if there isn't one
+fn param_end(chars: &[char]) -> usize {
+    match chars.first() {
+        Some(c) if is_name_start(*c) => name_end(chars, 0),
+        Some(c) if c.is_ascii_digit() => chars.iter().take_while(|c| c.is_ascii_digit()).count(),
+        Some(c) if is_special(*c) => 1,
+        _ => 0,
+    }
+}
+
+// index just past the variable name starting at chars[start]
+fn name_end(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+        i += 1;
+    }
+    i
+}
+
+// Brace expansion works on the raw word before anything else, so a{b,c} becomes ab ac.
+// Quoted braces and ${...} are left alone, and so is anything that isn't a list or a sequence.
+fn brace_expand(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '\'' => i = find_char(&chars, i + 1, '\'') + 1,
+            '"' => i = find_dquote_end(&chars, i + 1) + 1,
+            '`' => i = find_backtick_end(&chars, i + 1) + 1,
+            '$' => i = skip_dollar(&chars, i),
+            '{' => {
+                if let Some((end, alts)) = brace_group(&chars, i) {
+                    let prefix: String = chars[..i].iter().collect();
+                    let suffix: String = chars[end + 1..].iter().collect();
+                    // the prefix has nothing left to expand, but the alternatives and suffix might
+                    return alts.iter().flat_map(|alt| brace_expand(&format!("{}{}{}", prefix, alt, suffix))).collect();
+                }
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+    vec![word.to_string()]
+}
+
+// The alternatives of the brace group opening at chars[start] and the index of its closing brace
+fn brace_group(chars: &[char], start: usize) -> Option<(usize, Vec<String>)> {
+    let mut i = start + 1;
+    let mut depth = 1;
+    let mut commas = Vec::new();
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' => i = find_char(chars, i + 1, '\''),
+            '"' => i = find_dquote_end(chars, i + 1),
+            '`' => i = find_backtick_end(chars, i + 1),
+            '$' => {
+                i = skip_dollar(chars, i);
+                continue;
+            }
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            ',' if depth == 1 => commas.push(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    if i >= chars.len() {
+        return None;
+    }
+
+    if commas.is_empty() {
+        let inner: String = chars[start + 1..i].iter().collect();
+        return sequence(&inner).map(|alts| (i, alts));
+    }
+    let mut alts = Vec::new();
+    let mut from = start + 1;
+    for c in commas.into_iter().chain(std::iter::once(i)) {
+        alts.push(chars[from..c].iter().collect());
+        from = c + 1;
+    }
+    Some((i, alts))
+}
+
+// {1..10}, {10..1..2}, {01..10} or {a..e}
+fn sequence(inner: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = inner.split("..").collect();
+    if parts.len() != 2 && parts.len() != 3 {
+        return None;
+    }
+    let step = match parts.get(2) {
+        Some(s) => s.parse::<i64>().ok()?.checked_abs()?.max(1),
+        None => 1,
+    };
+
+    if let (Ok(a), Ok(b)) = (parts[0].parse::<i64>(), parts[1].parse::<i64>()) {
+        // a leading zero on either end pads every number to the same width
+        let padded = |s: &str| s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0');
+        let width = if padded(parts[0]) || padded(parts[1]) { parts[0].len().max(parts[1].len()) } else { 0 };
+        return Some(range(a, b, step).map(|n| format!("{:0width$}", n, width = width)).collect());
+    }
+
+    let single = |s: &str| {
+        let mut it = s.chars();
+        match (it.next(), it.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => Some(c as i64),
+            _ => None,
+        }
+    };
+    let (a, b) = (single(parts[0])?, single(parts[1])?);
+    Some(range(a, b, step).map(|n| (n as u8 as char).to_string()).collect())
+}
+
+// a to b inclusive, counting down if b is smaller
+fn range(a: i64, b: i64, step: i64) -> impl Iterator<Item = i64> {
+    let count = (a as i128 - b as i128).abs() / step as i128 + 1;
+    let dir = if b < a { -step } else { step };
+    (0..count).map(move |k| (a as i128 + k * dir as i128) as i64)
+}
+
+// The lexer has already checked that quotes and braces are balanced, so these just find the end.
+// Running off the end is treated as if it was closed there.
+
+fn find_char(chars: &[char], start: usize, c: char) -> usize {
+    chars[start..].iter().position(|x| *x == c).map_or(chars.len(), |p| start + p)
+}
+
+fn find_dquote_end(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '"' => return i,
+            '\\' => i += 2,
+            '`' => i = find_backtick_end(chars, i + 1) + 1,
+            '$' => i = skip_dollar(chars, i),
+            _ => i += 1,
+        }
+    }
+    i.min(chars.len())
+}
+
+// index just past ${...} or $(...) starting at chars[i], or past the $ if it's neither
+fn skip_dollar(chars: &[char], i: usize) -> usize {
+    match chars.get(i + 1) {
+        Some('{') => find_brace_end(chars, i + 2) + 1,
+        Some('(') => find_paren_end(chars, i + 2) + 1,
+        _ => i + 1,
+    }
+}
+
+// start is just after the opening ${
+fn find_brace_end(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '}' => return i,
+            '\\' => i += 2,
+            '\'' => i = find_char(chars, i + 1, '\'') + 1,
+            '"' => i = find_dquote_end(chars, i + 1) + 1,
+            '`' => i = find_backtick_end(chars, i + 1) + 1,
+            '$' => i = skip_dollar(chars, i),
+            _ => i += 1,
+        }
+    }
+    i.min(chars.len())
+}
+
+// start is just after the opening $(, nested parens are counted
+fn find_paren_end(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    let mut depth = 1;
+    while i < chars.len() {
+        match chars[i] {
+            '(' => {
+                depth += 1;
+                i += 1;
+            }
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+                i += 1;
+            }
+            '\\' => i += 2,
+            '\'' => i = find_char(chars, i + 1, '\'') + 1,
+            '"' => i = find_dquote_end(chars, i + 1) + 1,
+            '`' => i = find_backtick_end(chars, i + 1) + 1,
+            '$' => i = skip_dollar(chars, i),
+            _ => i += 1,
+        }
+    }
+    i.min(chars.len())
+}
+
+fn find_backtick_end(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '`' => return i,
+            '\\' => i += 2,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths;
+    use std::env;
+
+    fn words(ctx: &mut Context, s: &[&str]) -> Vec<String> {
+        expand_words(ctx, &s.iter().map(|w| w.to_string()).collect::<Vec<String>>()).unwrap()
+    }
+
+    #[test]
+    fn test_quote_removal() {
+        let mut ctx = Context::new();
+        assert_eq!(expand_word(&mut ctx, "plain").unwrap(), "plain");
+        assert_eq!(expand_word(&mut ctx, "'a | b'").unwrap(), "a | b");
+        assert_eq!(expand_word(&mut ctx, "foo\"bar baz\"'qux'").unwrap(), "foobar bazqux");
+        assert_eq!(expand_word(&mut ctx, "\"it's\"").unwrap(), "it's");
+        assert_eq!(expand_word(&mut ctx, "~/Notes").unwrap(), [paths::get_user_home(), "Notes".to_string()].join("/"));
+        assert_eq!(expand_word(&mut ctx, "'~'").unwrap(), "~");
+        assert_eq!(expand_word(&mut ctx, "a~b").unwrap(), "a~b");
+        assert_eq!(words(&mut ctx, &["''", "\"\""]), vec!["", ""]);
+    }
+
+    #[test]
+    fn test_assignment_tilde() {
+        let mut ctx = Context::new();
+        ctx.set_var("HOME", "/h").unwrap();
+        assert_eq!(expand_assignment(&mut ctx, "~/x").unwrap(), "/h/x");
+        assert_eq!(
+            expand_assignment(&mut ctx, "~/bin:~:/usr/bin:~/.local/bin").unwrap(),
+            "/h/bin:/h:/usr/bin:/h/.local/bin"
+        );
+        assert_eq!(expand_assignment(&mut ctx, "a:'~/x':\\~:b~:~x").unwrap(), "a:~/x:~:b~:~x");
+        // only assignments expand after ':'
+        assert_eq!(expand_word(&mut ctx, "a:~/x").unwrap(), "a:~/x");
+    }
+
+    #[test]
+    fn test_backslashes() {
+        let mut ctx = Context::new();
+        ctx.set_var("YUI_TEST_ESC", "v").unwrap();
+        assert_eq!(
+            words(&mut ctx, &["a\\ b", "\\|", "\\\"hi\\\"", "\\$YUI_TEST_ESC", "\\\\"]),
+            vec!["a b", "|", "\"hi\"", "$YUI_TEST_ESC", "\\"]
+        );
+        assert_eq!(words(&mut ctx, &["\"\\$YUI_TEST_ESC \\\" \\a\""]), vec!["$YUI_TEST_ESC \" \\a"]);
+        assert_eq!(
+            words(&mut ctx, &["'\\n'", "\\*", "\\{a,b}", "${YUI_TEST_UNSET_ESC:-\\}}"]),
+            vec!["\\n", "*", "{a,b}", "}"]
+        );
+        assert_eq!(expand_heredoc(&mut ctx, "\\$YUI_TEST_ESC \\\"\n").unwrap(), "$YUI_TEST_ESC \\\"\n");
+    }
+
+    #[test]
+    fn test_parameters() {
+        let mut ctx = Context::new();
+        ctx.set_var("YUI_TEST_A", "one two").unwrap();
+        ctx.unset_var("YUI_TEST_UNSET").unwrap();
+        assert_eq!(words(&mut ctx, &["$YUI_TEST_A"]), vec!["one", "two"]);
+        assert_eq!(words(&mut ctx, &["\"$YUI_TEST_A\""]), vec!["one two"]);
+        assert_eq!(words(&mut ctx, &["'$YUI_TEST_A'"]), vec!["$YUI_TEST_A"]);
+        assert_eq!(words(&mut ctx, &["x${YUI_TEST_A}y"]), vec!["xone", "twoy"]);
+        assert_eq!(words(&mut ctx, &["$YUI_TEST_UNSET", "a"]), vec!["a"]);
+        assert_eq!(words(&mut ctx, &["\"$YUI_TEST_UNSET\""]), vec![""]);
+        assert_eq!(words(&mut ctx, &["${#YUI_TEST_A}"]), vec!["7"]);
+        assert_eq!(words(&mut ctx, &["$", "a$", "$-"]), vec!["$", "a$", "$-"]);
+    }
+
+    #[test]
+    fn test_parameter_operators() {
+        let mut ctx = Context::new();
+        ctx.unset_var("YUI_TEST_DEF").unwrap();
+        assert_eq!(words(&mut ctx, &["${YUI_TEST_DEF:-a b}"]), vec!["a", "b"]);
+        assert_eq!(words(&mut ctx, &["\"${YUI_TEST_DEF:-a b}\""]), vec!["a b"]);
+        assert_eq!(words(&mut ctx, &["${YUI_TEST_DEF:-'$HOME'}"]), vec!["$HOME"]);
+        assert_eq!(
+            expand_word(&mut ctx, "${YUI_TEST_DEF:?not set}"),
+            Err(ExpandError("YUI_TEST_DEF: not set".to_string()))
+        );
+        assert_eq!(words(&mut ctx, &["${YUI_TEST_DEF:=set now}"]), vec!["set", "now"]);
+        assert_eq!(ctx.get_var("YUI_TEST_DEF"), Some("set now".to_string()));
+        assert_eq!(words(&mut ctx, &["${YUI_TEST_DEF:-other}"]), vec!["set", "now"]);
+        assert!(expand_word(&mut ctx, "${YUI_TEST_DEF/a/b}").is_err());
+        assert!(expand_word(&mut ctx, "${}").is_err());
+    }
+
+    #[test]
+    fn test_special_parameters() {
+        let mut ctx = Context::new();
+        ctx.laststatus = 3;
+        ctx.arg0 = "script.sh".to_string();
+        ctx.args = vec!["a b".to_string(), "c".to_string()];
+        assert_eq!(words(&mut ctx, &["$?", "$#", "$0", "${#}"]), vec!["3", "2", "script.sh", "2"]);
+        assert_eq!(words(&mut ctx, &["$1", "$2", "$3", "\"${1}x\"", "\"$10\""]), vec!["a", "b", "c", "a bx", "a b0"]);
+        assert_eq!(words(&mut ctx, &["\"$@\""]), vec!["a b", "c"]);
+        assert_eq!(words(&mut ctx, &["\"-$@-\""]), vec!["-a b", "c-"]);
+        assert_eq!(words(&mut ctx, &["\"$*\""]), vec!["a b c"]);
+        assert_eq!(words(&mut ctx, &["$@"]), vec!["a", "b", "c"]);
+        assert_eq!(words(&mut ctx, &["${#1}", "${3:-none}"]), vec!["3", "none"]);
+        assert_eq!(words(&mut ctx, &["$$"]), vec![std::process::id().to_string()]);
+        assert!(expand_word(&mut ctx, "${3:=x}").is_err());
+        ctx.args.clear();
+        assert_eq!(words(&mut ctx, &["\"$@\"", "x"]), vec!["x"]);
+    }
+
+    #[test]
+    fn test_command_substitution_syntax() {
+        // running substitutions is tested through the binary in tests/shell.rs
+        let mut ctx = Context::new();
+        assert!(expand_word(&mut ctx, "$(|)").is_err());
+    }
+
+    #[test]
+    fn test_brace_expansion() {
+        let mut ctx = Context::new();
+        assert_eq!(words(&mut ctx, &["a{b,c}d"]), vec!["abd", "acd"]);
+        assert_eq!(words(&mut ctx, &["{a,b}{1,2}"]), vec!["a1", "a2", "b1", "b2"]);
+        assert_eq!(words(&mut ctx, &["x{a,b{1,2}}"]), vec!["xa", "xb1", "xb2"]);
+        assert_eq!(words(&mut ctx, &["{,un}do"]), vec!["do", "undo"]);
+        assert_eq!(words(&mut ctx, &["{1..4}"]), vec!["1", "2", "3", "4"]);
+        assert_eq!(words(&mut ctx, &["{3..-1..2}"]), vec!["3", "1", "-1"]);
+        assert_eq!(words(&mut ctx, &["{08..10}"]), vec!["08", "09", "10"]);
+        assert_eq!(words(&mut ctx, &["{a..c}"]), vec!["a", "b", "c"]);
+        assert_eq!(
+            words(&mut ctx, &["{a}", "{}", "{1..}", "'{a,b}'", "\"{a,b}\""]),
+            vec!["{a}", "{}", "{1..}", "{a,b}", "{a,b}"]
+        );
+        ctx.set_var("YUI_TEST_BRACE", "v").unwrap();
+        assert_eq!(words(&mut ctx, &["${YUI_TEST_BRACE}{x,'y z'}"]), vec!["vx", "vy z"]);
+    }
+
+    #[test]
+    fn test_globbing() {
+        let mut ctx = Context::new();
+        let dir = env::temp_dir().join(format!("yui-expand-glob-{}", std::process::id()));
+        let d = dir.to_str().unwrap().to_string();
+        std::fs::create_dir_all(&dir).unwrap();
+        for f in ["a.o", "b.o", "c.rs"].iter() {
+            std::fs::File::create(dir.join(f)).unwrap();
+        }
+        ctx.set_var("YUI_TEST_GLOB", &format!("{}/*.rs", d)).unwrap();
+
+        assert_eq!(words(&mut ctx, &[&format!("{}/*.o", d)]), vec![format!("{}/a.o", d), format!("{}/b.o", d)]);
+        assert_eq!(
+            words(&mut ctx, &[&format!("{}/{{c,nope}}.*", d)]),
+            vec![format!("{}/c.rs", d), format!("{}/nope.*", d)]
+        );
+        assert_eq!(words(&mut ctx, &["$YUI_TEST_GLOB"]), vec![format!("{}/c.rs", d)]);
+        assert_eq!(words(&mut ctx, &["\"$YUI_TEST_GLOB\""]), vec![format!("{}/*.rs", d)]);
+        assert_eq!(words(&mut ctx, &[&format!("'{}/*.o'", d)]), vec![format!("{}/*.o", d)]);
+        assert_eq!(words(&mut ctx, &[&format!("{}/'*'.o", d)]), vec![format!("{}/*.o", d)]);
+
+        let nomatch = format!("{}/*.nope", d);
+        assert_eq!(words(&mut ctx, &[&nomatch]), vec![nomatch.clone()]);
+        ctx.config.glob_nomatch = NoMatch::Null;
+        assert_eq!(words(&mut ctx, &[&nomatch, "x"]), vec!["x"]);
+        ctx.config.glob_nomatch = NoMatch::Error;
+        assert_eq!(
+            expand_words(&mut ctx, std::slice::from_ref(&nomatch)),
+            Err(ExpandError(format!("no matches found: {}", nomatch)))
+        );
+        // only commands get globbed, not redirection targets
+        assert_eq!(expand_word(&mut ctx, &nomatch).unwrap(), nomatch);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut ctx = Context::new();
+        ctx.set_var("YUI_TEST_NUM", "6").unwrap();
+        assert_eq!(
+            words(&mut ctx, &["$((1 + 2))", "\"$(( YUI_TEST_NUM * 2 ))\"", "x$(($YUI_TEST_NUM/4))"]),
+            vec!["3", "12", "x1"]
+        );
+        assert_eq!(words(&mut ctx, &["$((YUI_TEST_NUM -= 1))"]), vec!["5"]);
+        assert_eq!(ctx.get_var("YUI_TEST_NUM"), Some("5".to_string()));
+        assert_eq!(expand_word(&mut ctx, "$((1 / 0))"), Err(ExpandError("1 / 0: division by zero".to_string())));
+    }
+
+    #[test]
+    fn test_heredoc() {
+        let mut ctx = Context::new();
+        ctx.set_var("YUI_TEST_DOC", "val").unwrap();
+        assert_eq!(expand_heredoc(&mut ctx, "'$YUI_TEST_DOC' \"x\"\n").unwrap(), "'val' \"x\"\n");
+    }
+}
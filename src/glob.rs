@@ -0,0 +1,281 @@
+// Pathname expansion for *, ?, [...] and **
+//
+// Patterns use a backslash to mark characters that came from quotes, so they are matched literally.
+use std::fs;
+
+pub fn is_meta(c: char) -> bool {
+    matches!(c, '*' | '?' | '[')
+}
+
+// All paths matching pattern, sorted. Empty if nothing matched.
+pub fn expand(pattern: &str) -> Vec<String> {
+    let mut paths = vec![String::new()];
+    let mut parts: Vec<&str> = pattern.split('/').collect();
+    if pattern.starts_with('/') {
+        paths = vec!["/".to_string()];
+        parts.remove(0);
+    }
+
+    let last = parts.len() - 1;
+    for (i, part) in parts.iter().enumerate() {
+        let chars: Vec<char> = part.chars().collect();
+        let mut next = Vec::new();
+        if part.is_empty() {
+            // trailing or doubled slash, only directories can have one
+            for p in paths.into_iter() {
+                if p.is_empty() || is_dir(&p) {
+                    next.push(format!("{}/", p.trim_end_matches('/')));
+                }
+            }
+        } else if *part == "**" {
+            for p in paths.into_iter() {
+                walk(&p, i == last, &mut next);
+            }
+        } else if !chars.iter().enumerate().any(|(j, c)| is_meta(*c) && !escaped(&chars, j)) {
+            let name = unescape(part);
+            for p in paths.into_iter() {
+                next.push(join(&p, &name));
+            }
+        } else {
+            for p in paths.into_iter() {
+                for name in list_dir(&p) {
+                    if matches(&chars, &name.chars().collect::<Vec<char>>()) {
+                        next.push(join(&p, &name));
+                    }
+                }
+            }
+        }
+        paths = next;
+        if paths.is_empty() {
+            return paths;
+        }
+    }
+
+    let mut found: Vec<String> =
+        paths.into_iter().filter(|p| !p.is_empty() && fs::symlink_metadata(p.trim_end_matches('/')).is_ok()).collect();
+    found.sort();
+    found.dedup();
+    found
+}
+
+// Does name match a single path component of a pattern
+pub fn matches(pattern: &[char], name: &[char]) -> bool {
+    // dotfiles are only matched by a literal leading dot
+    if name.first() == Some(&'.') && pattern.first() != Some(&'.') && !pattern.starts_with(&['\\', '.']) {
+        return false;
+    }
+    match_from(pattern, name)
+}
+
+fn match_from(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None; // where to retry after the last *
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match match_class(&pattern[p..], name[n]) {
+                Some((true, len)) => Some(len),
+                Some((false, _)) => None,
+                None if name[n] == '[' => Some(1), // unclosed bracket is literal
+                None => None,
+            },
+            Some('\\') if p + 1 < pattern.len() => {
+                if pattern[p + 1] == name[n] {
+                    Some(2)
+                } else {
+                    None
+                }
+            }
+            Some(c) if *c == name[n] => Some(1),
+            _ => None,
+        };
+        match step {
+            Some(len) => {
+                p += len;
+                n += 1;
+            }
+            None => match star {
+                Some((sp, sn)) => {
+                    p = sp;
+                    n = sn + 1;
+                    star = Some((sp, sn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Match c against the [...] at the start of pattern, returns (matched, length of the class)
+fn match_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+    let mut found = false;
+    let mut first = true;
+    while i < pattern.len() {
+        let mut lo = pattern[i];
+        if lo == ']' && !first {
+            return Some((found != negate, i + 1));
+        }
+        first = false;
+        if lo == '\\' && i + 1 < pattern.len() {
+            i += 1;
+            lo = pattern[i];
+        }
+        if pattern.get(i + 1) == Some(&'-') && matches!(pattern.get(i + 2), Some(h) if *h != ']') {
+            let mut hi_at = i + 2;
+            if pattern[hi_at] == '\\' && hi_at + 1 < pattern.len() {
+                hi_at += 1;
+            }
+            if lo <= c && c <= pattern[hi_at] {
+                found = true;
+            }
+            i = hi_at + 1;
+        } else {
+            if lo == c {
+                found = true;
+            }
+            i += 1;
+        }
+    }
+    None
+}
+
+fn escaped(chars: &[char], i: usize) -> bool {
+    chars[..i].iter().rev().take_while(|c| **c == '\\').count() % 2 == 1
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(n) = chars.next() {
+                out.push(n);
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn is_dir(path: &str) -> bool {
+    fs::metadata(path).map(|m| m.is_dir()).unwrap_or(false)
+}
+
+fn list_dir(dir: &str) -> Vec<String> {
+    let path = if dir.is_empty() { "." } else { dir };
+    match fs::read_dir(path) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).filter_map(|e| e.file_name().into_string().ok()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// ** matches the directory itself and every directory below it, or everything below it when it's
+// the last part of the pattern. Symlinks aren't followed so loops can't happen.
+fn walk(dir: &str, files_too: bool, out: &mut Vec<String>) {
+    if !dir.is_empty() && !is_dir(dir) {
+        return;
+    }
+    if !files_too || !dir.is_empty() {
+        out.push(dir.to_string());
+    }
+    let path = if dir.is_empty() { "." } else { dir };
+    let entries = match fs::read_dir(path) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = match entry.file_name().into_string() {
+            Ok(n) if !n.starts_with('.') => n,
+            _ => continue,
+        };
+        let full = join(dir, &name);
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => walk(&full, files_too, out),
+            _ if files_too => out.push(full),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs::File};
+
+    fn m(p: &str, n: &str) -> bool {
+        matches(&p.chars().collect::<Vec<char>>(), &n.chars().collect::<Vec<char>>())
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(m("*.o", "main.o"));
+        assert!(!m("*.o", "main.c"));
+        assert!(m("a*b*c", "aXXbYYc"));
+        assert!(!m("a*b*c", "aXXbYY"));
+        assert!(m("?.rs", "a.rs"));
+        assert!(!m("?.rs", "ab.rs"));
+        assert!(m("[abc]x", "bx"));
+        assert!(!m("[!abc]x", "bx"));
+        assert!(m("[!a-c]x", "dx"));
+        assert!(m("[a-z][0-9]", "q7"));
+        assert!(m("[]]", "]"));
+        assert!(m("\\*", "*"));
+        assert!(!m("\\*", "x"));
+        assert!(!m("*", ".hidden"));
+        assert!(m(".*", ".hidden"));
+        assert!(m("[", "["));
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = env::temp_dir().join(format!("yui-glob-test-{}", std::process::id()));
+        let d = dir.to_str().unwrap();
+        fs::create_dir_all(dir.join("sub/deep")).unwrap();
+        for f in ["a.o", "b.o", "c.rs", ".hidden.o", "sub/d.rs", "sub/deep/e.rs"].iter() {
+            File::create(dir.join(f)).unwrap();
+        }
+
+        assert_eq!(expand(&format!("{}/*.o", d)), vec![format!("{}/a.o", d), format!("{}/b.o", d)]);
+        assert_eq!(expand(&format!("{}/[ab].?", d)).len(), 2);
+        assert_eq!(expand(&format!("{}/*.nope", d)), Vec::<String>::new());
+        assert_eq!(expand(&format!("{}/*/", d)), vec![format!("{}/sub/", d)]);
+        assert_eq!(expand(&format!("{}/\\*.o", d)), Vec::<String>::new());
+        assert_eq!(
+            expand(&format!("{}/**/*.rs", d)),
+            vec![format!("{}/c.rs", d), format!("{}/sub/d.rs", d), format!("{}/sub/deep/e.rs", d)]
+        );
+        assert_eq!(
+            expand(&format!("{}/sub/**", d)),
+            vec![
+                format!("{}/sub", d),
+                format!("{}/sub/d.rs", d),
+                format!("{}/sub/deep", d),
+                format!("{}/sub/deep/e.rs", d)
+            ]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use crate::magic::MagicSpace;
 use crate::HINT_COLOR;
 use colored::*;
 use rustyline::{
@@ -5,26 +6,38 @@ use rustyline::{
     error::ReadlineError,
     highlight::{Highlighter, MatchingBracketHighlighter},
     hint::{Hinter, HistoryHinter},
-    validate::{MatchingBracketValidator, Validator},
+    line_buffer::LineBuffer,
+    validate::{ValidationContext, ValidationResult, Validator},
     Context,
 };
 use rustyline_derive::Helper;
-use std::borrow::Cow::{self, Borrowed, Owned};
+use std::borrow::Cow::{self, Owned};
 
 #[derive(Helper)]
 pub struct CustomHelper {
     pub completer: FilenameCompleter,
     pub highlighter: MatchingBracketHighlighter,
-    pub validator: MatchingBracketValidator,
     pub hinter: HistoryHinter,
+    pub magic: MagicSpace,
     //pub styled_prompt: String,
 }
 
 impl Completer for CustomHelper {
     type Candidate = Pair;
     fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Result<(usize, Vec<Pair>), ReadlineError> {
+        // magic space's expansion is the only candidate, see magic.rs
+        if let Some(text) = self.magic.pending() {
+            return Ok((pos, vec![Pair { display: text.clone(), replacement: text }]));
+        }
         self.completer.complete(line, pos, ctx)
     }
+
+    fn update(&self, line: &mut LineBuffer, start: usize, elected: &str) {
+        match self.magic.take_pending() {
+            Some((word_start, text)) => line.replace(word_start..line.pos(), &text),
+            None => self.completer.update(line, start, elected),
+        }
+    }
 }
 
 impl Highlighter for CustomHelper {
@@ -53,4 +66,12 @@ impl Hinter for CustomHelper {
     }
 }
 
-impl Validator for CustomHelper {}
+impl Validator for CustomHelper {
+    // keep reading lines while the parser says the input isn't finished, eg. for heredocs
+    fn validate(&self, ctx: &mut ValidationContext) -> Result<ValidationResult, ReadlineError> {
+        match crate::parser::parse(ctx.input(), None) {
+            Err(e) if e.is_incomplete() => Ok(ValidationResult::Incomplete),
+            _ => Ok(ValidationResult::Valid(None)),
+        }
+    }
+}
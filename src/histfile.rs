@@ -0,0 +1,588 @@
+// The shell's history: each line entered at the prompt with when, where and how it ran. The file
+// it's kept in is appended to under a lock, with each record written in one go, so several shells
+// can write to it at once without losing or mangling each other's lines. When it gets too long it's
+// rewritten with just the newest records, and renamed into place.
+//
+// After a header line, each record is a line of tab separated fields: the start time in seconds
+// since the epoch, how long it took in milliseconds, the exit status, the hostname, the session,
+// the working directory and the command. Unknown fields are empty, and backslashes, tabs and
+// newlines in fields are escaped with backslashes.
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::MetadataExt,
+    process,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use rustyline::{history::History, Cmd, ConditionalEventHandler, Event, EventContext, RepeatCount};
+
+use crate::config::Config;
+use crate::sys;
+
+const HEADER: &str = "#yui-history v1";
+const OLD_HEADER: &str = "#V2"; // rustyline's history files
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Record {
+    pub command: String,
+    pub start: Option<u64>,    // seconds since the epoch
+    pub duration: Option<u64>, // milliseconds
+    pub cwd: String,
+    pub status: Option<i32>,
+    pub host: String,
+    pub session: String,
+    pub saved: bool, // whether it's in the history file yet
+}
+
+pub struct Store {
+    records: Vec<Record>,
+    max_len: usize,
+    ignore_space: bool,
+    ignore_dups: bool,
+    host: String,
+    session: String,
+    current: Option<(Record, Instant)>, // the line being run
+    changed: bool,                      // since the line editor's copy was made
+    path: Option<String>,               // the history file, once loaded
+    file_id: Option<(u64, u64)>,        // its device and inode, to tell if it's been replaced
+    offset: u64,                        // how much of it has been read
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        let conf = Config::default();
+        Self {
+            records: Vec::new(),
+            max_len: conf.hist_max_size,
+            ignore_space: conf.hist_ign_space,
+            ignore_dups: conf.hist_ign_dups,
+            host: sys::hostname(),
+            session: format!("{}-{}", process::id(), now()),
+            current: None,
+            changed: false,
+            path: None,
+            file_id: None,
+            offset: 0,
+        }
+    }
+}
+
+impl Store {
+    pub fn configure(&mut self, conf: &Config) {
+        self.max_len = conf.hist_max_size;
+        self.ignore_space = conf.hist_ign_space;
+        self.ignore_dups = conf.hist_ign_dups;
+        self.trim();
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    // Read the history file at startup, converting it first if it's an old plain one
+    pub fn load(&mut self, path: &str) -> io::Result<()> {
+        self.path = Some(path.to_string());
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        // nothing can be appended while it's read and converted
+        sys::flock(&file, libc::LOCK_EX)?;
+        let mut text = Vec::new();
+        file.read_to_end(&mut text)?;
+        let (records, old) = parse_file(&String::from_utf8_lossy(&text));
+        let meta = if old { replace_file(path, &records)? } else { file.metadata()? };
+        self.file_id = Some(file_id(&meta));
+        self.offset = meta.len();
+        self.extend(records);
+        Ok(())
+    }
+
+    // Add the records other shells have written to the history file since it was last read,
+    // returning how many there were
+    pub fn merge(&mut self) -> io::Result<usize> {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return Ok(0),
+        };
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        sys::flock(&file, libc::LOCK_SH)?;
+        let meta = file.metadata()?;
+        // history -w in another shell replaces the file, so start again with what's in it now
+        let replaced = self.file_id.map(|id| id != file_id(&meta)).unwrap_or(false) || meta.len() < self.offset;
+        if replaced {
+            self.offset = 0;
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut text = Vec::new();
+        file.read_to_end(&mut text)?;
+        self.file_id = Some(file_id(&meta));
+        self.offset += text.len() as u64;
+        let text = String::from_utf8_lossy(&text);
+
+        if replaced {
+            let (records, _) = parse_file(&text);
+            let count = records.len();
+            let unsaved: Vec<Record> = self.records.drain(..).filter(|r| !r.saved).collect();
+            self.extend(records);
+            self.records.extend(unsaved);
+            self.trim();
+            return Ok(count);
+        }
+        // this shell's own records are already here
+        let session = &self.session;
+        let records: Vec<Record> = text.lines().filter_map(parse).filter(|r| &r.session != session).collect();
+        let count = records.len();
+        if count > 0 {
+            self.extend(records);
+        }
+        Ok(count)
+    }
+
+    // Add the records in a history file
+    pub fn read(&mut self, path: &str) -> io::Result<()> {
+        let mut file = File::open(path)?;
+        sys::flock(&file, libc::LOCK_SH)?;
+        let mut text = Vec::new();
+        file.read_to_end(&mut text)?;
+        let (records, _) = parse_file(&String::from_utf8_lossy(&text));
+        self.extend(records);
+        Ok(())
+    }
+
+    // Replace a file with the whole history
+    pub fn write(&mut self, path: &str) -> io::Result<()> {
+        // shells appending to the old file wait for this, and then append to the new one
+        let old = File::open(path).ok();
+        if let Some(old) = &old {
+            sys::flock(old, libc::LOCK_EX)?;
+        }
+        let meta = replace_file(path, &self.records)?;
+        if self.path.as_deref() == Some(path) {
+            self.file_id = Some(file_id(&meta));
+            self.offset = meta.len();
+        }
+        self.records.iter_mut().for_each(|r| r.saved = true);
+        Ok(())
+    }
+
+    // Append the records that aren't in the history file yet
+    pub fn append(&mut self, path: &str) -> io::Result<()> {
+        let unsaved: Vec<&Record> = self.records.iter().filter(|r| !r.saved).collect();
+        if !unsaved.is_empty() {
+            append_file(path, &unsaved, self.max_len)?;
+        }
+        self.records.iter_mut().for_each(|r| r.saved = true);
+        Ok(())
+    }
+
+    // Add a line that's about to run, returning false if it's not one to keep. It's written to
+    // path by finish once it's done.
+    pub fn start(&mut self, line: &str) -> bool {
+        if !self.wanted(line) {
+            return false;
+        }
+        let record = self.record(line);
+        self.current = Some((record.clone(), Instant::now()));
+        self.push(record);
+        true
+    }
+
+    pub fn finish(&mut self, status: Option<i32>, path: &str) -> io::Result<()> {
+        let (mut record, started) = match self.current.take() {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        // the line may have removed itself with history -c or -d, then it's not kept
+        let index = match self.records.iter().rposition(|r| r.start == record.start && r.command == record.command) {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        record.duration = Some(started.elapsed().as_millis() as u64);
+        record.status = status;
+        let result = append_file(path, &[&record], self.max_len);
+        record.saved = result.is_ok();
+        self.records[index] = record;
+        result
+    }
+
+    // Add a line without running it, it's written by append
+    pub fn add(&mut self, line: &str) {
+        if self.wanted(line) {
+            let record = self.record(line);
+            self.push(record);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.changed = true;
+    }
+
+    pub fn remove(&mut self, first: usize, last: usize) {
+        self.records.drain(first..=last);
+        self.changed = true;
+    }
+
+    // Give the line editor a copy of the commands if they've changed since it last had one. The
+    // editor keeps everything it's given, so entries are numbered the same in both.
+    pub fn sync(&mut self, editor: &mut History) {
+        if self.changed {
+            self.fill(editor);
+        }
+    }
+
+    pub fn fill(&mut self, editor: &mut History) {
+        editor.clear();
+        for record in &self.records {
+            editor.add(record.command.as_str());
+        }
+        self.changed = false;
+    }
+
+    fn wanted(&self, line: &str) -> bool {
+        !(self.max_len == 0
+            || line.trim().is_empty()
+            || (self.ignore_space && line.starts_with(char::is_whitespace))
+            || (self.ignore_dups && self.records.last().map(|r| r.command == line).unwrap_or(false)))
+    }
+
+    fn record(&self, line: &str) -> Record {
+        Record {
+            command: line.to_string(),
+            start: Some(now()),
+            cwd: env::current_dir().map(|d| d.to_string_lossy().into_owned()).unwrap_or_default(),
+            host: self.host.clone(),
+            session: self.session.clone(),
+            ..Record::default()
+        }
+    }
+
+    fn push(&mut self, record: Record) {
+        self.records.push(record);
+        self.trim();
+        self.changed = true;
+    }
+
+    fn extend(&mut self, records: Vec<Record>) {
+        self.records.extend(records.into_iter().map(|r| Record { saved: true, ..r }));
+        self.trim();
+        self.changed = true;
+    }
+
+    fn trim(&mut self) {
+        if self.records.len() > self.max_len {
+            let excess = self.records.len() - self.max_len;
+            self.records.drain(..excess);
+            self.changed = true;
+        }
+    }
+}
+
+// Alt-r asks for the history to be merged while a line is being edited. The handler can't get at
+// the history, so it ends the line like ctrl-c, leaving what was typed for the repl to carry on
+// editing once it has merged.
+#[derive(Clone, Default)]
+pub struct MergeKey {
+    requested: Arc<Mutex<Option<(String, usize)>>>,
+}
+
+impl MergeKey {
+    // The line and cursor position to go back to, if the key was pressed
+    pub fn take(&self) -> Option<(String, usize)> {
+        self.requested.lock().ok()?.take()
+    }
+}
+
+impl ConditionalEventHandler for MergeKey {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        *self.requested.lock().ok()? = Some((ctx.line().to_string(), ctx.pos()));
+        Some(Cmd::Interrupt)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn file_id(meta: &fs::Metadata) -> (u64, u64) {
+    (meta.dev(), meta.ino())
+}
+
+// The records in a file and whether it's in an old format, a plain list of lines or rustyline's
+fn parse_file(text: &str) -> (Vec<Record>, bool) {
+    let mut lines = text.lines();
+    let first = match lines.next() {
+        Some(line) => line,
+        None => return (Vec::new(), false),
+    };
+    if first == HEADER {
+        // a header can turn up again if shells started the file at the same time, and a line
+        // that doesn't parse is skipped rather than losing the rest
+        return (lines.filter_map(parse).collect(), false);
+    }
+    let escaped = first == OLD_HEADER;
+    let old = if escaped { None } else { Some(first) };
+    let commands = old.into_iter().chain(lines).map(|l| if escaped { unescape(l) } else { l.to_string() });
+    let records = commands.filter(|c| !c.is_empty()).map(|command| Record { command, ..Record::default() }).collect();
+    (records, true)
+}
+
+// Write records to a new file and rename it over path, so it's never seen half written. Returns
+// the new file's metadata.
+fn replace_file(path: &str, records: &[Record]) -> io::Result<fs::Metadata> {
+    let mut out = String::from(HEADER);
+    out.push('\n');
+    for record in records {
+        out.push_str(&format(record));
+        out.push('\n');
+    }
+    let tmp = format!("{}.{}.tmp", path, process::id());
+    let mut file = File::create(&tmp)?;
+    file.write_all(out.as_bytes())?;
+    let meta = file.metadata()?;
+    fs::rename(&tmp, path)?;
+    Ok(meta)
+}
+
+// Opened for appending, each write goes on the end even if another shell has written since. The
+// lock keeps out other writers and readers until the records are all there. So the file doesn't
+// grow forever, once it's over twice the size of the history it's cut back to the newest max_len.
+fn append_file(path: &str, records: &[&Record], max_len: usize) -> io::Result<()> {
+    let mut file = loop {
+        let file = OpenOptions::new().read(true).create(true).append(true).open(path)?;
+        sys::flock(&file, libc::LOCK_EX)?;
+        // it may have been replaced while waiting for the lock
+        match fs::metadata(path) {
+            Ok(meta) if file_id(&meta) == file_id(&file.metadata()?) => break file,
+            Ok(_) => continue,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    };
+    let mut text = Vec::new();
+    file.read_to_end(&mut text)?;
+    let count = text.iter().filter(|&&b| b == b'\n').count().saturating_sub(1); // less the header
+    if max_len > 0 && count + records.len() > 2 * max_len {
+        let (mut kept, _) = parse_file(&String::from_utf8_lossy(&text));
+        kept.extend(records.iter().map(|&r| r.clone()));
+        kept.drain(..kept.len().saturating_sub(max_len));
+        return replace_file(path, &kept).map(|_| ());
+    }
+
+    let mut out = String::new();
+    if text.is_empty() {
+        out.push_str(HEADER);
+        out.push('\n');
+    }
+    for record in records {
+        out.push_str(&format(record));
+        out.push('\n');
+    }
+    file.write_all(out.as_bytes())
+}
+
+fn format(r: &Record) -> String {
+    let opt = |n: Option<String>| n.unwrap_or_default();
+    [
+        opt(r.start.map(|s| s.to_string())),
+        opt(r.duration.map(|d| d.to_string())),
+        opt(r.status.map(|s| s.to_string())),
+        escape(&r.host),
+        escape(&r.session),
+        escape(&r.cwd),
+        escape(&r.command),
+    ]
+    .join("\t")
+}
+
+fn parse(line: &str) -> Option<Record> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 7 || fields[6].is_empty() {
+        return None;
+    }
+    // empty is unknown, anything else has to parse
+    fn num<T: std::str::FromStr>(s: &str) -> Option<Option<T>> {
+        if s.is_empty() {
+            Some(None)
+        } else {
+            s.parse().ok().map(Some)
+        }
+    }
+    Some(Record {
+        start: num(fields[0])?,
+        duration: num(fields[1])?,
+        status: num(fields[2])?,
+        host: unescape(fields[3]),
+        session: unescape(fields[4]),
+        cwd: unescape(fields[5]),
+        command: unescape(fields[6]),
+        saved: false,
+    })
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> String {
+        let path = env::temp_dir().join(format!("yui-{}-{}", name, process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_format() {
+        let record = Record {
+            command: "printf 'a\\tb\tc\n'".to_string(),
+            start: Some(1700000000),
+            duration: Some(12),
+            cwd: "/tmp/a\tb".to_string(),
+            status: Some(1),
+            host: "box".to_string(),
+            session: "12-1700000000".to_string(),
+            saved: false,
+        };
+        let line = format(&record);
+        assert_eq!(line, "1700000000\t12\t1\tbox\t12-1700000000\t/tmp/a\\tb\tprintf 'a\\\\tb\\tc\\n'");
+        assert_eq!(parse(&line), Some(record));
+
+        let unknown = Record { command: "ls".to_string(), ..Record::default() };
+        assert_eq!(format(&unknown), "\t\t\t\t\t\tls");
+        assert_eq!(parse("\t\t\t\t\t\tls"), Some(unknown));
+        assert_eq!(parse("x\t\t\t\t\t\tls"), None);
+        assert_eq!(parse("\t\t\t\t\t\t"), None);
+        assert_eq!(parse("1\t2\tls"), None);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let file = temp_file("migrate");
+        fs::write(&file, "#V2\nls -l\nprintf 'a\\\\nb'\\nc\n").unwrap();
+        let mut store = Store::default();
+        store.load(&file).unwrap();
+        let commands: Vec<&str> = store.records().iter().map(|r| r.command.as_str()).collect();
+        assert_eq!(commands, vec!["ls -l", "printf 'a\\nb'\nc"]);
+        assert!(fs::read_to_string(&file).unwrap().starts_with(HEADER));
+
+        // plain lines, from before rustyline's format had a header
+        fs::write(&file, "cd /\nmake\n").unwrap();
+        let mut store = Store::default();
+        store.load(&file).unwrap();
+        assert_eq!(store.records()[1], Record { command: "make".to_string(), saved: true, ..Record::default() });
+        let mut again = Store::default();
+        again.load(&file).unwrap();
+        assert_eq!(store.records(), again.records());
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_sessions() {
+        let file = temp_file("sessions");
+        let (mut one, mut two) = (Store::default(), Store::default());
+        two.session = "other".to_string();
+        assert!(one.start("echo one"));
+        assert!(two.start("echo two"));
+        two.finish(Some(0), &file).unwrap();
+        one.finish(Some(3), &file).unwrap();
+        assert!(!one.start(" secret"));
+        assert!(!one.start(""));
+
+        let mut store = Store::default();
+        store.load(&file).unwrap();
+        let records = store.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].command.as_str(), records[0].status), ("echo two", Some(0)));
+        assert_eq!((records[1].command.as_str(), records[1].status), ("echo one", Some(3)));
+        assert_eq!(records[0].session, "other");
+        assert_eq!(records[1].session, one.session);
+        assert_eq!(records[1], one.records()[0]);
+
+        // a line that clears the history isn't written either
+        assert!(one.start("history -c"));
+        one.clear();
+        one.finish(Some(0), &file).unwrap();
+        let mut store = Store::default();
+        store.load(&file).unwrap();
+        assert_eq!(store.records().len(), 2);
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_compact() {
+        let file = temp_file("compact");
+        let mut store = Store { max_len: 2, ..Store::default() };
+        for i in 1..=4 {
+            store.start(&format!("echo {}", i));
+            store.finish(Some(0), &file).unwrap();
+        }
+        let commands = || {
+            let (records, _) = parse_file(&fs::read_to_string(&file).unwrap());
+            records.into_iter().map(|r| r.command).collect::<Vec<String>>()
+        };
+        assert_eq!(commands(), vec!["echo 1", "echo 2", "echo 3", "echo 4"]);
+        store.start("echo 5");
+        store.finish(Some(0), &file).unwrap();
+        assert_eq!(commands(), vec!["echo 4", "echo 5"]);
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_merge() {
+        let file = temp_file("merge");
+        let (mut one, mut two) = (Store::default(), Store::default());
+        two.session = "other".to_string();
+        one.load(&file).unwrap();
+        two.load(&file).unwrap();
+        assert_eq!(one.merge().unwrap(), 0);
+
+        one.start("echo one");
+        one.finish(Some(0), &file).unwrap();
+        two.start("echo two");
+        two.finish(Some(0), &file).unwrap();
+        assert_eq!(one.merge().unwrap(), 1);
+        assert_eq!(two.merge().unwrap(), 1);
+        let commands = |s: &Store| s.records().iter().map(|r| r.command.clone()).collect::<Vec<String>>();
+        assert_eq!(commands(&one), vec!["echo one", "echo two"]);
+        assert_eq!(commands(&two), vec!["echo two", "echo one"]);
+        assert_eq!(one.merge().unwrap(), 0);
+
+        // history -w in one shell, the other starts again from the new file
+        two.add("unsaved");
+        one.clear();
+        one.add("only");
+        one.write(&file).unwrap();
+        assert_eq!(two.merge().unwrap(), 1);
+        assert_eq!(commands(&two), vec!["only", "unsaved"]);
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,376 @@
+// History expansion, done to interactive lines before they are parsed
+//
+// Events are numbered from 1, the oldest line in the history. The line being expanded isn't in the
+// history yet, so !! is the last entry.
+use rustyline::history::History;
+
+use crate::lexer::{self, TokenKind};
+
+// A line after history expansion
+#[derive(Debug, PartialEq)]
+pub struct Expansion {
+    pub line: String,
+    pub changed: bool,    // something was expanded, so the line is shown before it's run
+    pub print_only: bool, // :p was used, the line is shown and added to the history but not run
+}
+
+// Expand every !event in line, errors are ready to print after "yui: "
+pub fn expand(line: &str, history: &History) -> Result<Expansion, String> {
+    // ^old^new^ is a quick substitution in the last line, short for !!:s^old^new^
+    if line.starts_with('^') {
+        return expand(&format!("!!:s{}", line), history);
+    }
+    let chars: Vec<char> = line.chars().collect();
+    let mut exp = Expansion { line: String::new(), changed: false, print_only: false };
+    let (mut single, mut double) = (false, false);
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '\\' if !single && i + 1 < chars.len() => {
+                exp.line.push(c);
+                exp.line.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '!' if !single && starts_event(&chars, i, double) => {
+                let mut expander = Expander { chars: &chars, pos: i + 1, history };
+                let text = expander.designator(&mut exp.print_only)?;
+                exp.line.push_str(&text);
+                exp.changed = true;
+                i = expander.pos;
+                continue;
+            }
+            _ => {}
+        }
+        exp.line.push(c);
+        i += 1;
+    }
+    Ok(exp)
+}
+
+// Whether the ! at i starts a history expansion, not when it's on its own, before an operator, in
+// != or part of $!
+fn starts_event(chars: &[char], i: usize, double: bool) -> bool {
+    let prev = |n: usize| i.checked_sub(n).and_then(|j| chars.get(j).copied());
+    match chars.get(i + 1) {
+        None => false,
+        Some(c) if c.is_whitespace() || *c == '=' || lexer::is_meta(*c) => false,
+        Some('"') if double => false,
+        _ => prev(1) != Some('$') && !(prev(1) == Some('{') && prev(2) == Some('$')),
+    }
+}
+
+struct Expander<'a> {
+    chars: &'a [char],
+    pos: usize, // just after the !, then after whatever has been read
+    history: &'a History,
+}
+
+impl<'a> Expander<'a> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    // the text from start to the current position, for error messages
+    fn since(&self, start: usize) -> String {
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    // event [[:]word] [:modifier]...
+    fn designator(&mut self, print_only: &mut bool) -> Result<String, String> {
+        let start = self.pos - 1;
+        let event = self.event().ok_or_else(|| format!("{}: event not found", self.since(start)))?;
+        let mut text = self.words(&event)?;
+        while self.peek() == Some(':') && self.chars.get(self.pos + 1).filter(|c| c.is_ascii_alphabetic()).is_some() {
+            self.pos += 1;
+            text = self.modifier(text, print_only)?;
+        }
+        Ok(text)
+    }
+
+    // !!, !n, !-n, !?string[?] or !string, a lone ! before a word designator means the last event
+    fn event(&mut self) -> Option<String> {
+        match self.peek() {
+            Some('!') => {
+                self.pos += 1;
+                self.nth_last(1)
+            }
+            Some(':') | Some('^') | Some('$') | Some('*') => self.nth_last(1),
+            Some('-') | Some('0'..='9') => {
+                let negative = self.peek() == Some('-');
+                if negative {
+                    self.pos += 1;
+                }
+                let n = self.digits()?;
+                if negative {
+                    self.nth_last(n)
+                } else {
+                    self.history.get(n.checked_sub(1)?).cloned()
+                }
+            }
+            Some('?') => {
+                self.pos += 1;
+                let start = self.pos;
+                while self.peek().filter(|c| *c != '?' && *c != '\n').is_some() {
+                    self.pos += 1;
+                }
+                let needle = self.since(start);
+                if self.peek() == Some('?') {
+                    self.pos += 1;
+                }
+                self.history.iter().rev().find(|l| l.contains(&needle)).cloned()
+            }
+            _ => {
+                let start = self.pos;
+                while let Some(c) = self.peek() {
+                    if c.is_whitespace() || c == ':' || c == '"' || c == '\'' || lexer::is_meta(c) {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                let prefix = self.since(start);
+                if prefix.is_empty() {
+                    return None; // would match any line
+                }
+                self.history.iter().rev().find(|l| l.starts_with(&prefix)).cloned()
+            }
+        }
+    }
+
+    // the nth line from the end of the history, 1 is the last one
+    fn nth_last(&self, n: usize) -> Option<String> {
+        self.history.get(self.history.len().checked_sub(n)?).filter(|_| n > 0).cloned()
+    }
+
+    fn digits(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().filter(|c| c.is_ascii_digit()).is_some() {
+            self.pos += 1;
+        }
+        self.since(start).parse().ok()
+    }
+
+    // The words of the event to use: :n, :^, :$, :x-y, :x-, :-y, :x* or :*, all of them by default.
+    // The colon can be left out before ^, $ and *.
+    fn words(&mut self, event: &str) -> Result<String, String> {
+        let start = self.pos;
+        match (self.peek(), self.chars.get(self.pos + 1).copied()) {
+            (Some('^'), _) | (Some('$'), _) | (Some('*'), _) => {}
+            (Some(':'), Some(c)) if c.is_ascii_digit() || "^$*-".contains(c) => self.pos += 1,
+            _ => return Ok(event.to_string()),
+        }
+        let words = split_words(event);
+        let last = words.len().saturating_sub(1);
+        let range = if self.peek() == Some('*') {
+            self.pos += 1;
+            Some((1, Some(last)))
+        } else {
+            let first = if self.peek() == Some('-') { Some(0) } else { self.word_number(last) };
+            match self.peek() {
+                Some('*') => {
+                    self.pos += 1;
+                    first.map(|f| (f, Some(last)))
+                }
+                Some('-') => {
+                    self.pos += 1;
+                    // x- is like x* but without the last word
+                    let end = self.word_number(last).or_else(|| last.checked_sub(1));
+                    first.map(|f| (f, end))
+                }
+                _ => first.map(|f| (f, Some(f))),
+            }
+        };
+        match range {
+            // * with only the command name gives nothing rather than an error
+            Some((first, Some(end))) if first > 0 && first == end + 1 => Ok(String::new()),
+            Some((first, Some(end))) if first <= end && end <= last => Ok(words[first..=end].join(" ")),
+            _ => Err(format!("{}: bad word specifier", self.since(start))),
+        }
+    }
+
+    fn word_number(&mut self, last: usize) -> Option<usize> {
+        match self.peek() {
+            Some('^') => {
+                self.pos += 1;
+                Some(1)
+            }
+            Some('$') => {
+                self.pos += 1;
+                Some(last)
+            }
+            _ => self.digits(),
+        }
+    }
+
+    // :h, :t, :r, :e, :p or :s/old/new/, the colon has been read
+    fn modifier(&mut self, text: String, print_only: &mut bool) -> Result<String, String> {
+        let start = self.pos - 1;
+        let c = self.peek().unwrap_or_default();
+        self.pos += 1;
+        let name_start = text.rfind('/').map(|i| i + 1).unwrap_or(0);
+        let suffix = text[name_start..].rfind('.').map(|i| name_start + i);
+        match c {
+            'h' => Ok(text.rfind('/').map(|i| text[..i].to_string()).unwrap_or(text)),
+            't' => Ok(text[name_start..].to_string()),
+            'r' => Ok(suffix.map(|i| text[..i].to_string()).unwrap_or(text)),
+            'e' => Ok(suffix.map(|i| text[i..].to_string()).unwrap_or_default()),
+            'p' => {
+                *print_only = true;
+                Ok(text)
+            }
+            's' => {
+                let delim = self.peek().ok_or_else(|| format!("{}: substitution failed", self.since(start)))?;
+                self.pos += 1;
+                let old = self.until(delim);
+                let new = self.until(delim).replace('&', &old).replace('\u{0}', "&");
+                if old.is_empty() || !text.contains(&old) {
+                    return Err(format!("{}: substitution failed", self.since(start)));
+                }
+                Ok(text.replacen(&old, &new, 1))
+            }
+            _ => Err(format!("{}: unrecognized history modifier", self.since(start))),
+        }
+    }
+
+    // Read up to the next unescaped delim or the end of the line, \& is kept apart as a NUL so it
+    // doesn't get replaced
+    fn until(&mut self, delim: char) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == delim || c == '\n' {
+                break;
+            }
+            match (c, self.peek()) {
+                ('\\', Some(next)) if next == delim || next == '\\' => {
+                    self.pos += 1;
+                    s.push(next);
+                }
+                ('\\', Some('&')) => {
+                    self.pos += 1;
+                    s.push('\u{0}');
+                }
+                _ => s.push(c),
+            }
+        }
+        s
+    }
+}
+
+// Split a line into words the way the lexer does, with quotes kept and operators as words of their
+// own. Lines that don't lex are split on whitespace.
+fn split_words(line: &str) -> Vec<String> {
+    match lexer::tokenize(line) {
+        Ok(tokens) => tokens
+            .iter()
+            .filter(|t| t.kind != TokenKind::Newline)
+            .map(|t| line[t.span.start..t.span.end].to_string())
+            .collect(),
+        Err(_) => line.split_whitespace().map(|w| w.to_string()).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(lines: &[&str]) -> History {
+        let mut h = History::new();
+        for l in lines.iter() {
+            h.add(*l);
+        }
+        h
+    }
+
+    fn exp(h: &History, line: &str) -> String {
+        expand(line, h).unwrap().line
+    }
+
+    #[test]
+    fn test_events() {
+        let h = history(&["ls -l /tmp", "git commit -m 'a b'", "echo one two three"]);
+        assert_eq!(exp(&h, "!!"), "echo one two three");
+        assert_eq!(exp(&h, "sudo !! | wc"), "sudo echo one two three | wc");
+        assert_eq!(exp(&h, "!1"), "ls -l /tmp");
+        assert_eq!(exp(&h, "!-2;"), "git commit -m 'a b';");
+        assert_eq!(exp(&h, "!gi --amend"), "git commit -m 'a b' --amend");
+        assert_eq!(exp(&h, "!?tmp?"), "ls -l /tmp");
+        assert_eq!(exp(&h, "!?two"), "echo one two three");
+        assert_eq!(expand("!nope", &h), Err("!nope: event not found".to_string()));
+        assert_eq!(expand("!4", &h), Err("!4: event not found".to_string()));
+        assert_eq!(expand("!!", &History::new()), Err("!!: event not found".to_string()));
+        assert_eq!(expand("echo !'x'", &h), Err("!: event not found".to_string()));
+
+        let e = expand("echo plain", &h).unwrap();
+        assert!(!e.changed && !e.print_only);
+        assert!(expand("!!", &h).unwrap().changed);
+    }
+
+    #[test]
+    fn test_not_expanded() {
+        let h = history(&["true"]);
+        let lines = [
+            "echo '!!'",
+            "echo \\!!",
+            "[ a != b ]",
+            "! true",
+            "echo hi!",
+            "echo $! ${!x}",
+            "echo \"wow!\"",
+            "echo hi!;",
+            "echo hi!| cat",
+            "(echo a!)",
+            "a!&& b",
+            "echo a!>f",
+        ];
+        for line in lines.iter() {
+            assert_eq!(exp(&h, line), *line);
+        }
+        assert_eq!(exp(&h, "echo \"!!\""), "echo \"true\"");
+    }
+
+    #[test]
+    fn test_word_designators() {
+        let h = history(&["cp -r 'my dir' /backup/x.tar.gz", "ls"]);
+        assert_eq!(exp(&h, "echo !-2:0"), "echo cp");
+        assert_eq!(exp(&h, "echo !-2:$"), "echo /backup/x.tar.gz");
+        assert_eq!(exp(&h, "echo !-2^"), "echo -r");
+        assert_eq!(exp(&h, "echo !-2:2"), "echo 'my dir'");
+        assert_eq!(exp(&h, "echo !-2:1-2"), "echo -r 'my dir'");
+        assert_eq!(exp(&h, "echo !-2:2*"), "echo 'my dir' /backup/x.tar.gz");
+        assert_eq!(exp(&h, "echo !-2:1-"), "echo -r 'my dir'");
+        assert_eq!(exp(&h, "echo !-2:-1"), "echo cp -r");
+        assert_eq!(exp(&h, "echo !cp:*"), "echo -r 'my dir' /backup/x.tar.gz");
+        assert_eq!(exp(&h, "echo !$ !*"), "echo ls ");
+        assert_eq!(expand("echo !!:2", &h), Err(":2: bad word specifier".to_string()));
+        assert_eq!(expand("echo !-2:3-1", &h), Err(":3-1: bad word specifier".to_string()));
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let h = history(&["tar xf /src/pkg.tar.gz"]);
+        assert_eq!(exp(&h, "echo !$:h"), "echo /src");
+        assert_eq!(exp(&h, "echo !$:t"), "echo pkg.tar.gz");
+        assert_eq!(exp(&h, "echo !$:r"), "echo /src/pkg.tar");
+        assert_eq!(exp(&h, "echo !$:e"), "echo .gz");
+        assert_eq!(exp(&h, "echo !$:t:r:r"), "echo pkg");
+        assert_eq!(exp(&h, "!!:s/xf/tf/"), "tar tf /src/pkg.tar.gz");
+        assert_eq!(exp(&h, "!!:s|pkg|& old|:h"), "tar xf /src");
+        assert_eq!(exp(&h, "!!:s/tar/\\&/"), "& xf /src/pkg.tar.gz");
+        assert_eq!(exp(&h, "!!:s/gz/bz2"), "tar xf /src/pkg.tar.bz2");
+        assert_eq!(expand("!!:s/zip/x/", &h), Err(":s/zip/x/: substitution failed".to_string()));
+        assert_eq!(expand("!!:q", &h), Err(":q: unrecognized history modifier".to_string()));
+        assert_eq!(exp(&h, "^xf^tf^"), "tar tf /src/pkg.tar.gz");
+        assert_eq!(exp(&h, "^.gz^.bz2"), "tar xf /src/pkg.tar.bz2");
+        assert_eq!(exp(&h, "^tar^&s^:h"), "tars xf /src");
+        assert_eq!(expand("^zip^x", &h), Err(":s^zip^x: substitution failed".to_string()));
+        assert_eq!(exp(&h, "echo ^x^y"), "echo ^x^y");
+
+        let e = expand("!!:p", &h).unwrap();
+        assert_eq!(e.line, "tar xf /src/pkg.tar.gz");
+        assert!(e.print_only);
+    }
+}
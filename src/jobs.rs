@@ -0,0 +1,479 @@
+// Job control: the table of background and stopped jobs, and moving them between the foreground
+// and background
+use std::{
+    os::unix::io::RawFd,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use libc::{c_int, pid_t};
+
+use crate::sys::{self, ProcStatus};
+
+// signals the interactive shell ignores, the terminal sends them to the foreground job instead
+const SHELL_SIGNALS: [c_int; 5] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+// set when the terminal hangs up, so the jobs can be told before the shell exits
+static HUNG_UP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_hangup(_: c_int) {
+    HUNG_UP.store(true, Ordering::SeqCst);
+}
+
+pub fn hung_up() -> bool {
+    HUNG_UP.load(Ordering::SeqCst)
+}
+
+// The $? of a finished or stopped process, 128 + the signal if one ended it
+pub fn exit_status(status: ProcStatus) -> i32 {
+    match status {
+        ProcStatus::Exited(code) => code,
+        ProcStatus::Signaled(sig, _) | ProcStatus::Stopped(sig) => 128 + sig,
+    }
+}
+
+// What to tell the user about a process killed by sig. Nothing for an interrupt the user asked
+// for, or a broken pipe, which is how pipelines normally end early.
+pub fn signal_message(sig: c_int, core: bool) -> Option<String> {
+    if sig == libc::SIGINT || sig == libc::SIGPIPE {
+        return None;
+    }
+    let core = if core { " (core dumped)" } else { "" };
+    Some(format!("{}{}", sys::strsignal(sig), core))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done,
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: usize,                               // the n in %n, 0 until the job is in the table
+    pub pgid: pid_t,                             // 0 if the job shares the shell's process group
+    pub procs: Vec<(pid_t, Option<ProcStatus>)>, // None while the process is running
+    pub cmd: String,
+    notified: bool, // the user has been told about the current state
+}
+
+impl Job {
+    pub fn new(cmd: String) -> Self {
+        Self { id: 0, pgid: 0, procs: Vec::new(), cmd, notified: true }
+    }
+
+    // Record a started process, the first one leads the job's process group
+    pub fn add_process(&mut self, pid: pid_t, control: bool) {
+        if control {
+            if self.pgid == 0 {
+                self.pgid = pid;
+            }
+            // the child does this too, whichever runs first wins the race
+            let _ = sys::setpgid(pid, self.pgid);
+        }
+        self.procs.push((pid, None));
+    }
+
+    pub fn last_pid(&self) -> Option<pid_t> {
+        self.procs.last().map(|(pid, _)| *pid)
+    }
+
+    pub fn state(&self) -> JobState {
+        if self.procs.iter().all(|(_, s)| finished(s)) {
+            JobState::Done
+        } else if self.procs.iter().any(|(_, s)| matches!(s, Some(ProcStatus::Stopped(_)))) {
+            JobState::Stopped
+        } else {
+            JobState::Running
+        }
+    }
+
+    // The exit status of a job is the one of its last process
+    pub fn status(&self) -> i32 {
+        match self.procs.last() {
+            Some((_, Some(status))) => exit_status(*status),
+            _ => 0,
+        }
+    }
+
+    // Collect state changes. With block set, waits until every process has finished or stopped.
+    fn poll(&mut self, block: bool) {
+        let before = self.state();
+        for (pid, status) in self.procs.iter_mut() {
+            if finished(status) || (block && status.is_some()) {
+                continue;
+            }
+            match sys::wait_job(*pid, block) {
+                Ok(Some(s)) => *status = Some(s),
+                Ok(None) => {}
+                // not our child anymore, nothing more will be heard of it
+                Err(_) => *status = Some(ProcStatus::Exited(0)),
+            }
+        }
+        if self.state() != before {
+            self.notified = false;
+        }
+    }
+
+    fn signal(&self, sig: c_int) {
+        if self.pgid > 0 {
+            let _ = sys::kill(-self.pgid, sig);
+        } else {
+            for (pid, status) in self.procs.iter() {
+                if !finished(status) {
+                    let _ = sys::kill(*pid, sig);
+                }
+            }
+        }
+    }
+
+    // Continue a stopped job
+    fn resume(&mut self) {
+        for (_, status) in self.procs.iter_mut() {
+            if let Some(ProcStatus::Stopped(_)) = status {
+                *status = None;
+            }
+        }
+        self.signal(libc::SIGCONT);
+    }
+
+    // One line of `jobs` output, mark is + for the current job and - for the previous one
+    fn line(&self, mark: char, pids: bool) -> String {
+        let state = match (self.state(), self.procs.last()) {
+            (JobState::Running, _) => "Running".to_string(),
+            (JobState::Stopped, _) => "Stopped".to_string(),
+            (JobState::Done, Some((_, Some(ProcStatus::Exited(code))))) if *code != 0 => format!("Exit {}", code),
+            (JobState::Done, Some((_, Some(ProcStatus::Signaled(sig, core))))) => {
+                signal_message(*sig, *core).unwrap_or_else(|| sys::strsignal(*sig))
+            }
+            (JobState::Done, _) => "Done".to_string(),
+        };
+        let cmd = if self.state() == JobState::Running { format!("{} &", self.cmd) } else { self.cmd.clone() };
+        if pids {
+            let pids: Vec<String> = self.procs.iter().map(|(pid, _)| pid.to_string()).collect();
+            format!("[{}]{} {} {:<24}{}", self.id, mark, pids.join(" "), state, cmd)
+        } else {
+            format!("[{}]{}  {:<24}{}", self.id, mark, state, cmd)
+        }
+    }
+}
+
+fn finished(status: &Option<ProcStatus>) -> bool {
+    matches!(status, Some(ProcStatus::Exited(_)) | Some(ProcStatus::Signaled(..)))
+}
+
+// Where a new child should go, only used when job control is on
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessGroup {
+    pgid: pid_t, // 0 to start a new group led by the child
+    foreground: bool,
+    tty: RawFd,
+}
+
+impl ProcessGroup {
+    // Join the job's group and take the terminal if it's in the foreground. Runs in the child
+    // between fork and exec, so only makes async-signal-safe calls.
+    pub fn join(&self) {
+        let pgid = if self.pgid == 0 { sys::getpid() } else { self.pgid };
+        let _ = sys::setpgid(0, pgid);
+        if self.foreground {
+            let _ = sys::tcsetpgrp(self.tty, pgid);
+        }
+    }
+}
+
+// Children get back the default handling of the signals the shell ignores or catches
+pub fn reset_signals() {
+    for sig in SHELL_SIGNALS.iter() {
+        sys::default_signal(*sig);
+    }
+    sys::default_signal(libc::SIGHUP);
+}
+
+#[derive(Clone, Default)]
+pub struct JobTable {
+    pub control: bool, // only on for an interactive shell that owns its terminal
+    tty: RawFd,
+    shell_pgid: pid_t,
+    shell_modes: Option<libc::termios>,
+    jobs: Vec<Job>,
+}
+
+impl JobTable {
+    // Take over the terminal so jobs can be moved between the foreground and background
+    pub fn enable(&mut self) {
+        if !sys::isatty(0) {
+            return;
+        }
+        // if we were started in the background, wait until we're brought to the foreground
+        loop {
+            let pgrp = sys::getpgrp();
+            if sys::tcgetpgrp(0) == pgrp {
+                break;
+            }
+            let _ = sys::kill(-pgrp, libc::SIGTTIN);
+        }
+        for sig in SHELL_SIGNALS.iter() {
+            sys::ignore_signal(*sig);
+        }
+        sys::catch_signal(libc::SIGHUP, on_hangup);
+        // fails if we already lead a session, in which case we lead our group as well
+        let _ = sys::setpgid(0, 0);
+        self.tty = match sys::dup_high(0) {
+            Some(fd) => fd,
+            None => return,
+        };
+        self.shell_pgid = sys::getpgrp();
+        let _ = sys::tcsetpgrp(self.tty, self.shell_pgid);
+        self.shell_modes = sys::tcgetattr(self.tty);
+        self.control = true;
+    }
+
+    // A forked copy of the shell doesn't manage the parent's jobs
+    pub fn forked(&mut self) {
+        *self = JobTable::default();
+    }
+
+    pub fn group(&self, pgid: pid_t, foreground: bool) -> Option<ProcessGroup> {
+        if self.control {
+            Some(ProcessGroup { pgid, foreground, tty: self.tty })
+        } else {
+            None
+        }
+    }
+
+    // Put a job in the table, giving it a number if it doesn't have one yet
+    pub fn add(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        }
+        let id = job.id;
+        self.jobs.push(job);
+        id
+    }
+
+    // Index of the job named by a job spec: %n, %+, %-, %prefix or %?text. None is the current job.
+    pub fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let no_such = || format!("{}: no such job", spec.unwrap_or("current"));
+        let spec = match spec {
+            None | Some("%") | Some("%%") | Some("%+") => {
+                return self.jobs.len().checked_sub(1).ok_or_else(no_such);
+            }
+            Some("%-") => return self.jobs.len().checked_sub(2).ok_or_else(no_such),
+            Some(s) => s.strip_prefix('%').unwrap_or(s),
+        };
+        let found = if let Ok(n) = spec.parse::<usize>() {
+            self.jobs.iter().position(|j| j.id == n)
+        } else if let Some(text) = spec.strip_prefix('?') {
+            self.jobs.iter().rposition(|j| j.cmd.contains(text))
+        } else {
+            self.jobs.iter().rposition(|j| j.cmd.starts_with(spec))
+        };
+        found.ok_or_else(no_such)
+    }
+
+    // Index of the job that process pid belongs to
+    pub fn find_pid(&self, pid: pid_t) -> Option<usize> {
+        self.jobs.iter().position(|j| j.procs.iter().any(|(p, _)| *p == pid))
+    }
+
+    pub fn take(&mut self, index: usize) -> Job {
+        self.jobs.remove(index)
+    }
+
+    // + marks the current job, the one fg and bg use by default, and - the one before it
+    fn mark(&self, index: usize) -> char {
+        if index + 1 == self.jobs.len() {
+            '+'
+        } else if index + 2 == self.jobs.len() {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    // Give the terminal to a job and wait until it finishes or stops, then take the terminal back.
+    // A stopped job goes (back) into the table.
+    pub fn wait_fg(&mut self, mut job: Job, resume: bool) -> i32 {
+        if self.control && job.pgid > 0 {
+            let _ = sys::tcsetpgrp(self.tty, job.pgid);
+        }
+        if resume {
+            job.resume();
+        }
+        job.poll(true);
+        if self.control {
+            let _ = sys::tcsetpgrp(self.tty, self.shell_pgid);
+            if let Some(modes) = &self.shell_modes {
+                sys::tcsetattr(self.tty, modes);
+            }
+        }
+
+        if job.state() == JobState::Stopped {
+            job.notified = true;
+            self.add(job);
+            eprintln!("\n{}", self.jobs[self.jobs.len() - 1].line('+', false));
+            return 128 + libc::SIGTSTP;
+        }
+        if let Some((_, Some(ProcStatus::Signaled(sig, core)))) = job.procs.last() {
+            match signal_message(*sig, *core) {
+                Some(msg) => eprintln!("{}", msg),
+                // the terminal has echoed ^C, move past it
+                None if *sig == libc::SIGINT => println!(),
+                None => {}
+            }
+        }
+        job.status()
+    }
+
+    // Continue a job in the background
+    pub fn resume_bg(&mut self, index: usize) {
+        self.jobs[index].resume();
+        let line = format!("[{}]{} {} &", self.jobs[index].id, self.mark(index), self.jobs[index].cmd);
+        eprintln!("{}", line);
+    }
+
+    // Wait for the job at index to finish and drop it from the table, returning its status
+    pub fn wait_done(&mut self, index: usize) -> i32 {
+        while self.jobs[index].state() == JobState::Running {
+            self.jobs[index].poll(true);
+        }
+        let job = &self.jobs[index];
+        if job.state() == JobState::Done {
+            self.jobs.remove(index).status()
+        } else {
+            job.status()
+        }
+    }
+
+    // Wait for every job that's running and drop the finished ones, leaving stopped jobs in the
+    // table like bash does
+    pub fn wait_all(&mut self) {
+        let mut i = 0;
+        while i < self.jobs.len() {
+            let before = self.jobs.len();
+            if self.jobs[i].state() != JobState::Stopped {
+                self.wait_done(i);
+            }
+            if self.jobs.len() == before {
+                i += 1;
+            }
+        }
+    }
+
+    // Collect state changes of every job without blocking
+    pub fn update(&mut self) {
+        for job in self.jobs.iter_mut() {
+            job.poll(false);
+        }
+    }
+
+    // Print jobs that finished or stopped since we last looked, forgetting about finished ones.
+    // Called before each prompt.
+    pub fn notify(&mut self) {
+        self.update();
+        for i in 0..self.jobs.len() {
+            if !self.jobs[i].notified && self.jobs[i].state() != JobState::Running {
+                eprintln!("{}", self.jobs[i].line(self.mark(i), false));
+                self.jobs[i].notified = true;
+            }
+        }
+        self.jobs.retain(|j| j.state() != JobState::Done);
+    }
+
+    // Send SIGHUP to every job before the shell exits, waking stopped ones so they get it
+    pub fn hangup(&self) {
+        for job in self.jobs.iter() {
+            job.signal(libc::SIGHUP);
+            if job.state() == JobState::Stopped {
+                job.signal(libc::SIGCONT);
+            }
+        }
+    }
+
+    // Every job for the jobs builtin, pids adds the process ids
+    pub fn list(&mut self, pids: bool) -> Vec<String> {
+        self.update();
+        let lines = self.jobs.iter().enumerate().map(|(i, j)| j.line(self.mark(i), pids)).collect();
+        for job in self.jobs.iter_mut() {
+            job.notified = true;
+        }
+        self.jobs.retain(|j| j.state() != JobState::Done);
+        lines
+    }
+
+    // The process group leader of every job, or its first process without job control
+    pub fn leaders(&self) -> Vec<pid_t> {
+        self.jobs.iter().filter_map(|j| if j.pgid > 0 { Some(j.pgid) } else { j.procs.first().map(|p| p.0) }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn spawn_job(table: &mut JobTable, cmd: &str) -> usize {
+        // reaped through the job table instead of Child::wait
+        let pid = Command::new("sh").args(["-c", cmd]).spawn().unwrap().id();
+        let mut job = Job::new(cmd.to_string());
+        job.add_process(pid as pid_t, false);
+        table.add(job)
+    }
+
+    #[test]
+    fn test_find() {
+        let mut table = JobTable::default();
+        for cmd in ["sleep 0", "true", "exit 3"].iter() {
+            spawn_job(&mut table, cmd);
+        }
+        assert_eq!(table.find(None), Ok(2));
+        assert_eq!(table.find(Some("%-")), Ok(1));
+        assert_eq!(table.find(Some("%1")), Ok(0));
+        assert_eq!(table.find(Some("2")), Ok(1));
+        assert_eq!(table.find(Some("%sl")), Ok(0));
+        assert_eq!(table.find(Some("%?it")), Ok(2));
+        assert_eq!(table.find(Some("%9")), Err("%9: no such job".to_string()));
+
+        assert_eq!(table.wait_done(2), 3);
+        assert_eq!(table.wait_done(1), 0);
+        assert_eq!(table.add(Job::new("next".to_string())), 2);
+    }
+
+    #[test]
+    fn test_states() {
+        let mut table = JobTable::default();
+        spawn_job(&mut table, "kill -STOP $$; exit 4");
+        let i = table.find(Some("%1")).unwrap();
+        while table.jobs[i].state() == JobState::Running {
+            table.update();
+        }
+        assert_eq!(table.jobs[i].state(), JobState::Stopped);
+        assert_eq!(table.list(false), vec![format!("[1]+  {:<24}kill -STOP $$; exit 4", "Stopped")]);
+
+        // a bare wait doesn't wait for stopped jobs
+        spawn_job(&mut table, "exit 2");
+        table.wait_all();
+        assert_eq!(table.jobs.len(), 1);
+        assert_eq!(table.jobs[i].state(), JobState::Stopped);
+
+        table.jobs[i].resume();
+        assert_eq!(table.wait_done(i), 4);
+        assert!(table.jobs.is_empty());
+    }
+
+    #[test]
+    fn test_signals() {
+        let mut table = JobTable::default();
+        spawn_job(&mut table, "kill -KILL $$");
+        spawn_job(&mut table, "sleep 10");
+        assert_eq!(table.wait_done(0), 128 + libc::SIGKILL);
+
+        table.hangup();
+        assert_eq!(table.wait_done(0), 128 + libc::SIGHUP);
+
+        assert_eq!(signal_message(libc::SIGSEGV, true), Some("Segmentation fault (core dumped)".to_string()));
+        assert_eq!(signal_message(libc::SIGTERM, false), Some("Terminated".to_string()));
+        assert_eq!(signal_message(libc::SIGINT, false), None);
+    }
+}
@@ -0,0 +1,604 @@
+use std::fmt;
+
+use crate::parser::ParseError;
+
+// Byte offsets into the source line, used for error reporting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Pipe,      // |
+    AndIf,     // &&
+    OrIf,      // ||
+    Semi,      // ;
+    Amp,       // &
+    LParen,    // (
+    RParen,    // )
+    Less,      // <
+    DLess,     // <<
+    DLessDash, // <<-
+    TLess,     // <<<
+    Great,     // >
+    DGreat,    // >>
+    LessAnd,   // <&
+    GreatAnd,  // >&
+    AndGreat,  // &>
+    AndDGreat, // &>>
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Op::Pipe => "|",
+            Op::AndIf => "&&",
+            Op::OrIf => "||",
+            Op::Semi => ";",
+            Op::Amp => "&",
+            Op::LParen => "(",
+            Op::RParen => ")",
+            Op::Less => "<",
+            Op::DLess => "<<",
+            Op::DLessDash => "<<-",
+            Op::TLess => "<<<",
+            Op::Great => ">",
+            Op::DGreat => ">>",
+            Op::LessAnd => "<&",
+            Op::GreatAnd => ">&",
+            Op::AndGreat => "&>",
+            Op::AndDGreat => "&>>",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    // raw word text, quotes are kept so expansion knows what was quoted
+    Word(String),
+    // a number directly in front of a redirection, eg. the 2 in 2>file
+    IoNumber(i32),
+    Op(Op),
+    Newline,
+    // replaces the delimiter word after << once the body has been read
+    HereDoc { body: String, quoted: bool },
+    // the raw expression of an ((...)) command
+    Arith(String),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Word(w) => write!(f, "{}", w),
+            TokenKind::IoNumber(n) => write!(f, "{}", n),
+            TokenKind::Op(o) => write!(f, "{}", o),
+            TokenKind::Newline => write!(f, "newline"),
+            TokenKind::HereDoc { .. } => write!(f, "<<"),
+            TokenKind::Arith(_) => write!(f, "(("),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    Lexer { src: input, pos: 0, heredoc_next: None, heredocs: Vec::new() }.run()
+}
+
+// A here-document whose body starts on the line after the next newline
+struct PendingHereDoc {
+    token: usize, // index of the delimiter token
+    strip_tabs: bool,
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,                 // current byte offset
+    heredoc_next: Option<bool>, // the next word is a heredoc delimiter, true for <<-
+    heredocs: Vec<PendingHereDoc>,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.src[self.pos..].chars().nth(n)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn run(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = Vec::new();
+        while let Some(c) = self.peek() {
+            let start = self.pos;
+            match c {
+                ' ' | '\t' | '\r' => {
+                    self.bump();
+                }
+                '\\' if self.continuation()? => {}
+                '\n' => {
+                    self.bump();
+                    self.heredoc_next = None;
+                    tokens.push(Token { kind: TokenKind::Newline, span: Span::new(start, self.pos) });
+                    self.read_heredocs(&mut tokens)?;
+                }
+                // comments run until the end of the line
+                '#' => {
+                    while let Some(c) = self.peek() {
+                        if c == '\n' {
+                            break;
+                        }
+                        self.bump();
+                    }
+                }
+                // a subshell starting with another one needs a space, ( (...) )
+                '(' if self.peek_nth(1) == Some('(') => {
+                    let expr = self.arith()?;
+                    tokens.push(Token { kind: TokenKind::Arith(expr), span: Span::new(start, self.pos) });
+                }
+                _ if is_meta(c) => {
+                    let op = self.operator();
+                    self.heredoc_next = match op {
+                        Op::DLess => Some(false),
+                        Op::DLessDash => Some(true),
+                        _ => None,
+                    };
+                    tokens.push(Token { kind: TokenKind::Op(op), span: Span::new(start, self.pos) });
+                }
+                _ => {
+                    let word = self.word()?;
+                    let kind = match self.peek() {
+                        Some('<') | Some('>') if word.chars().all(|c| c.is_ascii_digit()) => {
+                            match word.parse::<i32>() {
+                                Ok(n) => TokenKind::IoNumber(n),
+                                Err(_) => TokenKind::Word(word),
+                            }
+                        }
+                        _ => TokenKind::Word(word),
+                    };
+                    if let Some(strip_tabs) = self.heredoc_next.take() {
+                        self.heredocs.push(PendingHereDoc { token: tokens.len(), strip_tabs });
+                    }
+                    tokens.push(Token { kind, span: Span::new(start, self.pos) });
+                }
+            }
+        }
+        if !self.heredocs.is_empty() {
+            return Err(ParseError::Incomplete("here-document is missing its delimiter"));
+        }
+        Ok(tokens)
+    }
+
+    // Read the bodies of the heredocs started on the line that just ended
+    fn read_heredocs(&mut self, tokens: &mut [Token]) -> Result<(), ParseError> {
+        for doc in std::mem::take(&mut self.heredocs).into_iter() {
+            let raw = match &tokens[doc.token].kind {
+                TokenKind::Word(w) => w.clone(),
+                _ => continue,
+            };
+            let quoted = raw.contains(['\'', '"', '\\']);
+            let delim: String = raw.chars().filter(|c| !matches!(c, '\'' | '"' | '\\')).collect();
+
+            let mut body = String::new();
+            loop {
+                if self.pos >= self.src.len() {
+                    return Err(ParseError::Incomplete("here-document is missing its delimiter"));
+                }
+                let rest = &self.src[self.pos..];
+                let (line, len) = match rest.find('\n') {
+                    Some(i) => (&rest[..i], i + 1),
+                    None => (rest, rest.len()),
+                };
+                self.pos += len;
+                let line = if doc.strip_tabs { line.trim_start_matches('\t') } else { line };
+                if line == delim {
+                    break;
+                }
+                body.push_str(line);
+                body.push('\n');
+            }
+            tokens[doc.token].kind = TokenKind::HereDoc { body, quoted };
+        }
+        Ok(())
+    }
+
+    fn operator(&mut self) -> Op {
+        let c = self.bump().unwrap();
+        let next = self.peek();
+        let (op, extra) = match (c, next) {
+            ('|', Some('|')) => (Op::OrIf, 1),
+            ('|', _) => (Op::Pipe, 0),
+            ('&', Some('&')) => (Op::AndIf, 1),
+            ('&', Some('>')) if self.peek_nth(1) == Some('>') => (Op::AndDGreat, 2),
+            ('&', Some('>')) => (Op::AndGreat, 1),
+            ('&', _) => (Op::Amp, 0),
+            (';', _) => (Op::Semi, 0),
+            ('(', _) => (Op::LParen, 0),
+            (')', _) => (Op::RParen, 0),
+            ('<', Some('&')) => (Op::LessAnd, 1),
+            ('<', Some('<')) => match (self.peek_nth(1), self.peek_nth(2)) {
+                (Some('<'), _) => (Op::TLess, 2),
+                (Some('-'), _) => (Op::DLessDash, 2),
+                _ => (Op::DLess, 1),
+            },
+            ('<', _) => (Op::Less, 0),
+            ('>', Some('>')) => (Op::DGreat, 1),
+            ('>', Some('&')) => (Op::GreatAnd, 1),
+            ('>', Some('|')) => (Op::Great, 1), // clobber, we never refuse to overwrite anyway
+            ('>', _) => (Op::Great, 0),
+            _ => unreachable!("is_meta and operator() disagree"),
+        };
+        for _ in 0..extra {
+            self.bump();
+        }
+        op
+    }
+
+    // A backslash-newline is removed entirely, joining the lines around it. Returns whether there
+    // was one, and asks for another line if it's the last thing in the input.
+    fn continuation(&mut self) -> Result<bool, ParseError> {
+        match self.peek_nth(1) {
+            Some('\n') => {
+                self.bump();
+                self.bump();
+            }
+            None => {
+                self.bump();
+            }
+            _ => return Ok(false),
+        }
+        if self.pos >= self.src.len() {
+            return Err(ParseError::Incomplete("line continuation"));
+        }
+        Ok(true)
+    }
+
+    // A backslash and the character it escapes, both kept in the word
+    fn escaped(&mut self, word: &mut String) {
+        word.push(self.bump().unwrap());
+        if let Some(c) = self.bump() {
+            word.push(c);
+        }
+    }
+
+    // Read a single word, keeping any quoting intact
+    fn word(&mut self) -> Result<String, ParseError> {
+        let mut word = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' | '\n' => break,
+                _ if is_meta(c) => break,
+                '\\' if self.continuation()? => {}
+                '\\' => self.escaped(&mut word),
+                '\'' => self.single_quoted(&mut word)?,
+                '"' => self.double_quoted(&mut word)?,
+                '$' if self.peek_nth(1) == Some('{') => self.braced(&mut word)?,
+                '$' if self.peek_nth(1) == Some('(') => self.subst(&mut word)?,
+                '`' => self.backticks(&mut word)?,
+                _ => {
+                    self.bump();
+                    word.push(c);
+                }
+            }
+        }
+        Ok(word)
+    }
+
+    fn single_quoted(&mut self, word: &mut String) -> Result<(), ParseError> {
+        word.push(self.bump().unwrap());
+        loop {
+            match self.bump() {
+                Some(c) => {
+                    word.push(c);
+                    if c == '\'' {
+                        return Ok(());
+                    }
+                }
+                None => return Err(ParseError::Incomplete("unterminated quote")),
+            }
+        }
+    }
+
+    fn double_quoted(&mut self, word: &mut String) -> Result<(), ParseError> {
+        word.push(self.bump().unwrap());
+        loop {
+            match self.peek() {
+                Some('\\') if self.continuation()? => {}
+                Some('\\') if self.peek_nth(1).is_some() => self.escaped(word),
+                Some('$') if self.peek_nth(1) == Some('{') => self.braced(word)?,
+                Some('$') if self.peek_nth(1) == Some('(') => self.subst(word)?,
+                Some('`') => self.backticks(word)?,
+                Some(c) => {
+                    self.bump();
+                    word.push(c);
+                    if c == '"' {
+                        return Ok(());
+                    }
+                }
+                None => return Err(ParseError::Incomplete("unterminated quote")),
+            }
+        }
+    }
+
+    // ${...}, which may contain spaces and quotes of its own
+    fn braced(&mut self, word: &mut String) -> Result<(), ParseError> {
+        word.push(self.bump().unwrap()); // $
+        word.push(self.bump().unwrap()); // {
+        loop {
+            match self.peek() {
+                Some('}') => {
+                    word.push(self.bump().unwrap());
+                    return Ok(());
+                }
+                Some('\'') => self.single_quoted(word)?,
+                Some('"') => self.double_quoted(word)?,
+                Some('\\') => self.escaped(word),
+                Some('$') if self.peek_nth(1) == Some('{') => self.braced(word)?,
+                Some('$') if self.peek_nth(1) == Some('(') => self.subst(word)?,
+                Some('`') => self.backticks(word)?,
+                Some(c) => {
+                    self.bump();
+                    word.push(c);
+                }
+                None => return Err(ParseError::Incomplete("unterminated ${")),
+            }
+        }
+    }
+
+    // $(...) and $((...)), counting parens so nested substitutions end in the right place
+    fn subst(&mut self, word: &mut String) -> Result<(), ParseError> {
+        word.push(self.bump().unwrap()); // $
+        word.push(self.bump().unwrap()); // (
+        let mut depth = 1;
+        loop {
+            match self.peek() {
+                Some('\'') => self.single_quoted(word)?,
+                Some('"') => self.double_quoted(word)?,
+                Some('`') => self.backticks(word)?,
+                Some('\\') => self.escaped(word),
+                Some('$') if self.peek_nth(1) == Some('{') => self.braced(word)?,
+                Some('$') if self.peek_nth(1) == Some('(') => self.subst(word)?,
+                Some(c) => {
+                    self.bump();
+                    word.push(c);
+                    if c == '(' {
+                        depth += 1;
+                    } else if c == ')' {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(());
+                        }
+                    }
+                }
+                None => return Err(ParseError::Incomplete("unterminated $(")),
+            }
+        }
+    }
+
+    // ((...)), the expression is everything up to the )) matching the opening parens
+    fn arith(&mut self) -> Result<String, ParseError> {
+        self.bump();
+        self.bump();
+        let mut expr = String::new();
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                Some(')') if depth == 0 => {
+                    let start = self.pos;
+                    self.bump();
+                    match self.bump() {
+                        Some(')') => return Ok(expr),
+                        Some(_) => return Err(ParseError::Unexpected(")".to_string(), Span::new(start, start + 1))),
+                        None => return Err(ParseError::Incomplete("unterminated ((")),
+                    }
+                }
+                Some('\'') => self.single_quoted(&mut expr)?,
+                Some('"') => self.double_quoted(&mut expr)?,
+                Some('`') => self.backticks(&mut expr)?,
+                Some('$') if self.peek_nth(1) == Some('{') => self.braced(&mut expr)?,
+                Some('$') if self.peek_nth(1) == Some('(') => self.subst(&mut expr)?,
+                Some(c) => {
+                    self.bump();
+                    expr.push(c);
+                    if c == '(' {
+                        depth += 1;
+                    } else if c == ')' {
+                        depth -= 1;
+                    }
+                }
+                None => return Err(ParseError::Incomplete("unterminated ((")),
+            }
+        }
+    }
+
+    fn backticks(&mut self, word: &mut String) -> Result<(), ParseError> {
+        word.push(self.bump().unwrap());
+        loop {
+            match self.bump() {
+                Some('\\') => {
+                    word.push('\\');
+                    if let Some(c) = self.bump() {
+                        word.push(c);
+                    }
+                }
+                Some(c) => {
+                    word.push(c);
+                    if c == '`' {
+                        return Ok(());
+                    }
+                }
+                None => return Err(ParseError::Incomplete("unterminated `")),
+            }
+        }
+    }
+}
+
+pub fn is_meta(c: char) -> bool {
+    matches!(c, '|' | '&' | ';' | '<' | '>' | '(' | ')')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(s: &str) -> Vec<TokenKind> {
+        tokenize(s).unwrap().into_iter().map(|t| t.kind).collect()
+    }
+
+    fn word(s: &str) -> TokenKind {
+        TokenKind::Word(s.to_string())
+    }
+
+    #[test]
+    fn test_words_and_ops() {
+        assert_eq!(kinds("ls -al"), vec![word("ls"), word("-al")]);
+        assert_eq!(
+            kinds("make|tee log&&echo done"),
+            vec![
+                word("make"),
+                TokenKind::Op(Op::Pipe),
+                word("tee"),
+                word("log"),
+                TokenKind::Op(Op::AndIf),
+                word("echo"),
+                word("done")
+            ]
+        );
+        assert_eq!(
+            kinds("a || b ; c &"),
+            vec![
+                word("a"),
+                TokenKind::Op(Op::OrIf),
+                word("b"),
+                TokenKind::Op(Op::Semi),
+                word("c"),
+                TokenKind::Op(Op::Amp)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(
+            kinds("((i = (1 + 2) * 3)) && echo $((i))"),
+            vec![
+                TokenKind::Arith("i = (1 + 2) * 3".to_string()),
+                TokenKind::Op(Op::AndIf),
+                word("echo"),
+                word("$((i))")
+            ]
+        );
+        assert_eq!(kinds("( (a) )")[..2], [TokenKind::Op(Op::LParen), TokenKind::Op(Op::LParen)]);
+        assert!(tokenize("((1 + 2)").unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn test_quotes() {
+        assert_eq!(kinds("echo 'a | b' \"c && d\""), vec![word("echo"), word("'a | b'"), word("\"c && d\"")]);
+        assert_eq!(kinds("echo foo'bar baz'"), vec![word("echo"), word("foo'bar baz'")]);
+        assert!(tokenize("echo 'oops").unwrap_err().is_incomplete());
+        assert_eq!(
+            kinds("echo ${A:-a b} \"${B:-\"}\"}\""),
+            vec![word("echo"), word("${A:-a b}"), word("\"${B:-\"}\"}\"")]
+        );
+        assert!(tokenize("echo ${A:-x").unwrap_err().is_incomplete());
+        assert_eq!(
+            kinds("echo $(ls | wc -l) \"$(echo \")\")\""),
+            vec![word("echo"), word("$(ls | wc -l)"), word("\"$(echo \")\")\"")]
+        );
+        assert_eq!(
+            kinds("a=$(echo $(echo (x)))b `echo | cat`"),
+            vec![word("a=$(echo $(echo (x)))b"), word("`echo | cat`")]
+        );
+        assert!(tokenize("echo $(ls").unwrap_err().is_incomplete());
+        assert!(tokenize("echo `ls").unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(kinds("echo a\\ b \\| \\'x"), vec![word("echo"), word("a\\ b"), word("\\|"), word("\\'x")]);
+        assert_eq!(kinds("echo \"a \\\" b\""), vec![word("echo"), word("\"a \\\" b\"")]);
+        assert_eq!(kinds("echo $(echo \\)) ${A:-\\}}"), vec![word("echo"), word("$(echo \\))"), word("${A:-\\}}")]);
+        assert_eq!(kinds("echo a\\\nb \\\n c"), vec![word("echo"), word("ab"), word("c")]);
+        assert_eq!(kinds("echo \"a\\\nb\""), vec![word("echo"), word("\"ab\"")]);
+        assert!(tokenize("echo a \\").unwrap_err().is_incomplete());
+        assert!(tokenize("echo a \\\n").unwrap_err().is_incomplete());
+        assert!(tokenize("echo \"a \\").unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn test_redirections() {
+        assert_eq!(
+            kinds("cmd 2>&1 >out"),
+            vec![
+                word("cmd"),
+                TokenKind::IoNumber(2),
+                TokenKind::Op(Op::GreatAnd),
+                word("1"),
+                TokenKind::Op(Op::Great),
+                word("out")
+            ]
+        );
+        assert_eq!(
+            kinds("cmd &>> log 2 >x"),
+            vec![
+                word("cmd"),
+                TokenKind::Op(Op::AndDGreat),
+                word("log"),
+                word("2"),
+                TokenKind::Op(Op::Great),
+                word("x")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_heredocs() {
+        let toks = kinds("cat <<EOF; cat <<-'END'\nhello $USER\nEOF\n\t\tindented\n\tEND\necho done");
+        assert_eq!(
+            toks,
+            vec![
+                word("cat"),
+                TokenKind::Op(Op::DLess),
+                TokenKind::HereDoc { body: "hello $USER\n".to_string(), quoted: false },
+                TokenKind::Op(Op::Semi),
+                word("cat"),
+                TokenKind::Op(Op::DLessDash),
+                TokenKind::HereDoc { body: "indented\n".to_string(), quoted: true },
+                TokenKind::Newline,
+                word("echo"),
+                word("done")
+            ]
+        );
+        assert_eq!(kinds("cat <<< 'a b'"), vec![word("cat"), TokenKind::Op(Op::TLess), word("'a b'")]);
+        assert!(tokenize("cat <<EOF").unwrap_err().is_incomplete());
+        assert!(tokenize("cat <<EOF\nbody\nEO").unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn test_comments_and_spans() {
+        assert_eq!(kinds("ls # a comment | not a pipe"), vec![word("ls")]);
+        let toks = tokenize("ls  | wc").unwrap();
+        assert_eq!(toks[1].span, Span::new(4, 5));
+        assert_eq!(toks[2].span, Span::new(6, 8));
+    }
+}
@@ -0,0 +1,198 @@
+// Magic space: pressing space expands the word just typed, so what will run can be seen before
+// pressing enter. History references, aliases in command position and $VARs are expanded.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use rustyline::{
+    history::History, Cmd, ConditionalEventHandler, EditMode, Event, EventContext, InputMode, RepeatCount,
+};
+
+use crate::context::Context;
+use crate::expand;
+use crate::history;
+use crate::lexer;
+use crate::parser;
+
+// The key handler can't borrow the shell, so it works from a copy that's updated before each prompt
+#[derive(Clone, Default)]
+pub struct MagicSpace {
+    shared: Arc<Mutex<Snapshot>>,
+}
+
+#[derive(Default)]
+struct Snapshot {
+    aliases: HashMap<String, String>,
+    vars: HashMap<String, String>,
+    history: History,
+    pending: Option<(usize, String)>, // where the word starts and what replaces it
+}
+
+impl MagicSpace {
+    pub fn update(&self, ctx: &Context, history: &History) {
+        let mut copy = History::with_config(
+            rustyline::Config::builder()
+                .max_history_size(history.len().max(1))
+                .history_ignore_dups(false)
+                .history_ignore_space(false)
+                .build(),
+        );
+        for line in history.iter() {
+            copy.add(line.as_str());
+        }
+        if let Ok(mut snapshot) = self.shared.lock() {
+            snapshot.aliases = ctx.aliases.clone();
+            snapshot.vars = ctx.vars.iter().filter_map(|(n, v)| Some((n.clone(), v.value.clone()?))).collect();
+            snapshot.history = copy;
+        }
+    }
+
+    // The replacement waiting to be made by the completer
+    pub fn pending(&self) -> Option<String> {
+        self.shared.lock().ok()?.pending.as_ref().map(|(_, text)| text.clone())
+    }
+
+    pub fn take_pending(&self) -> Option<(usize, String)> {
+        self.shared.lock().ok()?.pending.take()
+    }
+}
+
+impl ConditionalEventHandler for MagicSpace {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        // space is a movement in vi's command mode
+        if ctx.mode() == EditMode::Vi && ctx.input_mode() != InputMode::Insert {
+            return None;
+        }
+        let mut snapshot = self.shared.lock().ok()?;
+        let before = &ctx.line()[..ctx.pos()];
+        let (word, expanded) = snapshot.expand_last(before)?;
+        // Cmd::Replace leaves the cursor in front of the new text, but a completion doesn't. So the
+        // replacement is handed to the helper's completer, which makes it when asked to complete.
+        snapshot.pending = Some((before.len() - word.len(), expanded + " "));
+        Some(Cmd::Complete)
+    }
+}
+
+impl Snapshot {
+    // The word ending at the end of before and what it expands to, None if it doesn't change
+    fn expand_last<'a>(&self, before: &'a str) -> Option<(&'a str, String)> {
+        let start = last_word_start(before)?;
+        let word = &before[start..];
+        if word.is_empty() {
+            return None;
+        }
+        let expanded = if word.contains('!') {
+            history::expand(word, &self.history).ok().filter(|e| e.changed)?.line
+        } else if let Some(alias) = self.aliases.get(word).filter(|_| command_position(&before[..start])) {
+            alias.clone()
+        } else {
+            self.expand_vars(word)?
+        };
+        Some((word, expanded))
+    }
+
+    // $NAME and ${NAME} outside single quotes, unset ones are left alone
+    fn expand_vars(&self, word: &str) -> Option<String> {
+        let chars: Vec<char> = word.chars().collect();
+        let mut out = String::new();
+        let mut single = false;
+        let mut changed = false;
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] == '\'' {
+                single = !single;
+            } else if chars[i] == '$' && !single {
+                let braced = chars.get(i + 1) == Some(&'{');
+                let start = if braced { i + 2 } else { i + 1 };
+                let mut end = start;
+                while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_') {
+                    end += 1;
+                }
+                let name: String = chars[start..end].iter().collect();
+                let closed = !braced || chars.get(end) == Some(&'}');
+                if let Some(value) = self.vars.get(&name).filter(|_| closed && expand::is_name(&name)) {
+                    out.push_str(value);
+                    changed = true;
+                    i = if braced { end + 1 } else { end };
+                    continue;
+                }
+            }
+            out.push(chars[i]);
+            i += 1;
+        }
+        if changed {
+            Some(out)
+        } else {
+            None
+        }
+    }
+}
+
+// Where the word being typed starts, None if it's inside an unfinished quote
+fn last_word_start(line: &str) -> Option<usize> {
+    let (mut single, mut double, mut escaped) = (false, false, false);
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && !single {
+            escaped = true;
+        } else if c == '\'' && !double {
+            single = !single;
+        } else if c == '"' && !single {
+            double = !double;
+        } else if !single && !double && (c.is_whitespace() || lexer::is_meta(c)) {
+            start = i + c.len_utf8();
+        }
+    }
+    if single || double || escaped {
+        None
+    } else {
+        Some(start)
+    }
+}
+
+// Whether a word after before would be a command name
+fn command_position(before: &str) -> bool {
+    let before = before.trim_end();
+    match before.chars().last() {
+        None => true,
+        Some(c) if lexer::is_meta(c) => true,
+        _ => before.split_whitespace().last().map(parser::is_reserved).unwrap_or(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let mut s = Snapshot::default();
+        s.aliases.insert("ll".to_string(), "ls -al".to_string());
+        s.vars.insert("DIR".to_string(), "/tmp/x".to_string());
+        s.history = History::new();
+        s.history.add("make test");
+        s
+    }
+
+    #[test]
+    fn test_expand_last() {
+        let s = snapshot();
+        assert_eq!(s.expand_last("sudo !!"), Some(("!!", "make test".to_string())));
+        assert_eq!(s.expand_last("echo !$"), Some(("!$", "test".to_string())));
+        assert_eq!(s.expand_last("ll"), Some(("ll", "ls -al".to_string())));
+        assert_eq!(s.expand_last("cd /; ll"), Some(("ll", "ls -al".to_string())));
+        assert_eq!(s.expand_last("if ll"), Some(("ll", "ls -al".to_string())));
+        assert_eq!(s.expand_last("cd $DIR/a"), Some(("$DIR/a", "/tmp/x/a".to_string())));
+        assert_eq!(s.expand_last("cd \"${DIR}\""), Some(("\"${DIR}\"", "\"/tmp/x\"".to_string())));
+
+        // nothing to do
+        assert_eq!(s.expand_last("echo ll"), None);
+        assert_eq!(s.expand_last("echo '$DIR'"), None);
+        assert_eq!(s.expand_last("echo $NOPE ${DIR"), None);
+        assert_eq!(s.expand_last("echo 'a !!"), None);
+        assert_eq!(s.expand_last("echo !nope"), None);
+        assert_eq!(s.expand_last("echo "), None);
+    }
+}
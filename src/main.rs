@@ -1,26 +1,39 @@
-use std::{env, fs::File, process::exit, sync::Mutex};
+use std::{env, process::exit, sync::Mutex};
 
-//use libc;
 use lazy_static::lazy_static;
 use regex::Regex;
 
 use rustyline::{
     completion::FilenameCompleter, error::ReadlineError, highlight::MatchingBracketHighlighter, hint::HistoryHinter,
-    validate::MatchingBracketValidator, Config, Editor,
+    Config, Editor, EventHandler, KeyEvent, Modifiers,
 };
 
 use colored::*;
 
+mod arith;
+mod ast;
 mod builtins;
 mod config;
 mod context;
+mod expand;
+mod glob;
 mod helper;
+mod histfile;
+mod history;
+mod jobs;
+mod lexer;
+mod magic;
 mod parser;
 mod paths;
+mod redirect;
 mod spawn;
+mod sys;
 
+use config::HistShare;
 use context::Context;
 use helper::CustomHelper;
+use histfile::MergeKey;
+use magic::MagicSpace;
 
 lazy_static! {
     static ref CHANGE_SET: Regex = Regex::new(r"^set\s.*").unwrap();
@@ -45,7 +58,7 @@ fn main() {
                 for (i, arg) in env::args().enumerate() {
                     if i == 0 || arg == "-c" {
                         continue;
-                    } else if to_run == "" {
+                    } else if to_run.is_empty() {
                         to_run.push_str(arg.as_str());
                     } else {
                         to_run.push(' ');
@@ -53,8 +66,8 @@ fn main() {
                     };
                 }
                 let mut context = context::Context::new();
-                spawn::choose_and_run(&mut context, false, parser::split_to_args(to_run));
-                return;
+                let status = spawn::run_line(&mut context, false, &to_run);
+                exit(context.exiting.unwrap_or(status));
             }
             _ => {
                 let re = Regex::new(r"-.*").unwrap();
@@ -62,7 +75,12 @@ fn main() {
                     eprintln!("Invalid arg: {}", arg);
                     return;
                 } else {
-                    parser::parse_file(&mut context::Context::new(), arg);
+                    // the rest of the args are for the script
+                    let mut context = context::Context::new();
+                    context.args = env::args().skip(2).collect();
+                    context.arg0 = arg.clone();
+                    parser::parse_file(&mut context, arg);
+                    exit(context.exiting.unwrap_or(context.laststatus));
                 }
             }
         }
@@ -70,12 +88,20 @@ fn main() {
 
     // Initialize config
     let mut context = context::Context::new();
+    context.jobs.enable();
     if let Some(f) = paths::get_user_config() {
         parser::parse_file(&mut context, f);
     }
+    if let Some(status) = context.exiting {
+        exit(status);
+    }
+    context.history.configure(&context.config);
+    if let Err(e) = context.history.load(&context.histfile) {
+        eprintln!("yui: {}: {}", context.histfile, e);
+    }
 
     loop {
-        if repl(&mut context) == true {
+        if repl(&mut context) {
             continue;
         } else {
             break;
@@ -84,55 +110,104 @@ fn main() {
 }
 
 fn repl(ctx: &mut Context) -> bool {
+    let magic = MagicSpace::default();
     let helper = CustomHelper {
         completer: FilenameCompleter::new(),
         highlighter: MatchingBracketHighlighter::new(),
-        validator: MatchingBracketValidator::new(),
         hinter: HistoryHinter {},
+        magic: magic.clone(),
         //styled_prompt: "".to_owned(),
     };
-    let mut rl = Editor::with_config(editor_config(ctx.clone()));
+    let mut rl = Editor::with_config(editor_config(&ctx.config));
     rl.set_helper(Some(helper));
-    if rl.load_history(&ctx.histfile).is_err() {
-        File::create(&ctx.histfile).expect("Could not create history file");
+    if ctx.config.magic_space {
+        rl.bind_sequence(KeyEvent::new(' ', Modifiers::NONE), EventHandler::Conditional(Box::new(magic.clone())));
+    }
+    let merge = MergeKey::default();
+    if ctx.config.hist_share != HistShare::Off {
+        rl.bind_sequence(KeyEvent::alt('r'), EventHandler::Conditional(Box::new(merge.clone())));
     }
+    ctx.history.configure(&ctx.config);
+    ctx.history.fill(rl.history_mut());
 
     // REPL
+    let mut editing: Option<(String, usize)> = None; // a line to carry on with after merging
     let ret: bool = loop {
         //let prompt = get_prompt(ctx);
         //rl.helper_mut().expect("No helper!").styled_prompt = prompt;
-        let readline = rl.readline(&get_prompt(ctx));
+        ctx.jobs.notify();
+        if ctx.config.hist_share == HistShare::Prompt {
+            merge_history(ctx);
+        }
+        ctx.history.sync(rl.history_mut());
+        if ctx.config.magic_space {
+            magic.update(ctx, rl.history());
+        }
+        let prompt = get_prompt(ctx);
+        let readline = match editing.take() {
+            Some((line, pos)) => rl.readline_with_initial(&prompt, (&line[..pos], &line[pos..])),
+            None => rl.readline(&prompt),
+        };
+        if jobs::hung_up() {
+            // the terminal is gone, take the jobs down with us
+            ctx.jobs.hangup();
+            exit(128 + libc::SIGHUP);
+        }
 
         match readline {
             Ok(line) => {
-                if line.trim() == "exit" {
-                    println!("Goodbye!");
-                    rl.save_history(&ctx.histfile).unwrap();
-                    exit(0);
-                } else if line.trim() == "" {
-                    // line is empty or whitespace only
-                    continue;
-                } else if line.trim().starts_with('#') {
-                    // line is a comment
-                    continue;
-                //TODO: Make this more reliable by matching later
-                } else if CHANGE_SET.is_match(&line.trim()) {
-                    spawn::choose_and_run(ctx, true, parser::split_to_args(line));
-                    rl.save_history(&ctx.histfile).unwrap();
-                    break true; // need to reload the line editor
+                let (line, added) = match history::expand(&line, rl.history()) {
+                    Ok(exp) => {
+                        if exp.changed {
+                            println!("{}", exp.line);
+                        }
+                        // the expanded line is what goes in the history
+                        let added = ctx.config.auto_add_history && ctx.history.start(&exp.line);
+                        if exp.print_only {
+                            finish_history(ctx, added, None);
+                            continue;
+                        }
+                        (exp.line, added)
+                    }
+                    Err(e) => {
+                        eprintln!("yui: {}", e);
+                        continue;
+                    }
+                };
+                let status = if line.trim() == "" || line.trim().starts_with('#') {
+                    // line is empty or a comment
+                    None
                 } else if line.trim() == "?" {
                     println!("Last exit code: {}", ctx.laststatus);
+                    None
                 } else {
-                    spawn::choose_and_run(ctx, true, parser::split_to_args(line));
+                    Some(spawn::run_line(ctx, true, &line))
+                };
+                finish_history(ctx, added, status);
+                if let Some(status) = ctx.exiting {
+                    println!("Goodbye!");
+                    ctx.jobs.hangup();
+                    save_history(ctx);
+                    exit(status);
+                }
+                //TODO: Make this more reliable by matching later
+                if CHANGE_SET.is_match(line.trim()) {
+                    break true; // need to reload the line editor
                 }
             }
             Err(ReadlineError::Interrupted) => {
-                println!("^c");
+                if let Some(line) = merge.take() {
+                    merge_history(ctx);
+                    editing = Some(line);
+                } else {
+                    println!("^c");
+                }
             }
             // exit on ^d
             Err(ReadlineError::Eof) => {
                 println!("^d... Goodbye!");
-                rl.save_history(&ctx.histfile).unwrap();
+                ctx.jobs.hangup();
+                save_history(ctx);
                 break false;
             }
             Err(e) => {
@@ -140,20 +215,39 @@ fn repl(ctx: &mut Context) -> bool {
             }
         }
     };
-    if ret == true {
-        true
-    } else {
-        false
+    ret
+}
+
+// Write the record of a line that's been added to the history now that it's done
+fn finish_history(ctx: &mut Context, added: bool, status: Option<i32>) {
+    if added {
+        if let Err(e) = ctx.history.finish(status, &ctx.histfile) {
+            eprintln!("yui: {}: {}", ctx.histfile, e);
+        }
+    }
+}
+
+// Pick up what other shells have added to the history file
+fn merge_history(ctx: &mut Context) {
+    if let Err(e) = ctx.history.merge() {
+        eprintln!("yui: {}: {}", ctx.histfile, e);
+    }
+}
+
+// Write out anything added without being run, with history -s
+fn save_history(ctx: &mut Context) {
+    if let Err(e) = ctx.history.append(&ctx.histfile) {
+        eprintln!("yui: {}: {}", ctx.histfile, e);
     }
 }
 
 fn get_prompt(ctx: &mut Context) -> String {
     let mut raw = ctx.config.prompt_string.to_owned();
     if raw.contains("{cwd}") {
-        let cwd: _ = paths::condense_home(&env::current_dir().unwrap().to_str().unwrap());
+        let cwd = paths::condense_home(env::current_dir().unwrap().to_str().unwrap());
         raw = raw.replace("{cwd}", &cwd);
     }
-    return raw;
+    raw
     //let mut all = Vec::<ColoredString>::new();
     //if raw.contains("{bold}") {
     //	let mut bold_result = Vec::new();
@@ -184,18 +278,18 @@ fn get_prompt(ctx: &mut Context) -> String {
     //}
 }
 
-fn editor_config(ctx: Context) -> Config {
+fn editor_config(conf: &config::Config) -> Config {
     //let conf = CONFIG.lock().unwrap();
-    let conf = ctx.config;
     Config::builder()
         .max_history_size(conf.hist_max_size)
-        .history_ignore_dups(conf.hist_ign_dups)
-        .history_ignore_space(conf.hist_ign_space)
+        // lines are filtered as they go into the shell's history, the editor gets a copy of it
+        .history_ignore_dups(false)
+        .history_ignore_space(false)
         .completion_type(conf.completion_type)
         .completion_prompt_limit(conf.completion_limit)
         .keyseq_timeout(conf.keyseq_timeout)
         .edit_mode(conf.edit_mode)
-        .auto_add_history(conf.auto_add_history)
+        .auto_add_history(false) // lines are added by the repl after history expansion
         .bell_style(conf.bell_style)
         .color_mode(conf.color_mode)
         .tab_stop(conf.tab_stop)
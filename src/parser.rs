@@ -1,6 +1,9 @@
 use std::{
+    collections::HashMap,
+    fmt,
     fs::File,
     io::{prelude::*, BufReader, ErrorKind},
+    ops::Range,
     path::Path,
     process::exit,
 };
@@ -8,19 +11,32 @@ use std::{
 use lazy_static::lazy_static;
 use regex::Regex;
 
+use crate::ast::*;
 use crate::config;
 use crate::context::Context;
-use crate::paths;
+use crate::expand;
+use crate::lexer::{self, Op, Span, Token, TokenKind};
 
 pub fn parse_file(ctx: &mut Context, path: impl AsRef<Path>) {
     let lines = split_file_lines(path);
     let mut in_setblock = false;
     let mut in_aliasblock = false;
+    let mut pending = String::new(); // a command that continues on the next line
+    let mut pending_start = 0;
     lazy_static! {
         static ref CHECK_COMMENT: Regex = Regex::new(r"^\#.*").unwrap();
     }
     for (i, s) in lines.iter().enumerate() {
-        if CHECK_COMMENT.is_match(&s) {
+        if !pending.is_empty() {
+            pending.push_str(s);
+            pending.push('\n');
+            run_pending(ctx, &mut pending, pending_start);
+            if ctx.exiting.is_some() {
+                return;
+            }
+            continue;
+        }
+        if CHECK_COMMENT.is_match(s) {
             // line is a comment
             continue;
         }
@@ -42,26 +58,53 @@ pub fn parse_file(ctx: &mut Context, path: impl AsRef<Path>) {
             in_aliasblock = false;
             continue;
         }
-        if in_aliasblock == true {
-            if config::aliasblock_parse_and_exec(ctx, s) == false {
+        if in_aliasblock {
+            if !config::aliasblock_parse_and_exec(ctx, s) {
                 eprintln!("yuirc: Stopped parsing at line {}, Invalid syntax: \"{}\"", i + 1, s);
                 return;
             } else {
                 continue;
             }
         }
-        if in_setblock == true {
-            if config::setblock_parse_and_exec(ctx, s) == false {
+        if in_setblock {
+            if !config::setblock_parse_and_exec(ctx, s) {
                 eprintln!("yuirc: Stopped parsing at line {}, Invalid syntax: \"{}\"", i + 1, s);
                 return;
             } else {
                 continue;
             }
         }
-        crate::spawn::choose_and_run(ctx, false, split_to_args(s.to_string()));
+        pending_start = i + 1;
+        pending.push_str(s);
+        pending.push('\n');
+        run_pending(ctx, &mut pending, pending_start);
+        if ctx.exiting.is_some() {
+            return;
+        }
+    }
+    if !pending.is_empty() {
+        if let Err(e) = parse(&pending, None) {
+            eprintln!("yui: line {}: {}", pending_start, e);
+            ctx.laststatus = 2;
+        }
     }
 }
 
+// Run the buffered lines once they form a complete command
+fn run_pending(ctx: &mut Context, pending: &mut String, line: usize) {
+    match parse(pending, None) {
+        Ok(list) => {
+            crate::spawn::run_list(ctx, &list);
+        }
+        Err(e) if e.is_incomplete() => return, // keep reading lines
+        Err(e) => {
+            eprintln!("yui: line {}: {}", line, e);
+            ctx.laststatus = 2;
+        }
+    }
+    pending.clear();
+}
+
 // read files line by line, putting each line into a vector
 pub fn split_file_lines(path: impl AsRef<Path>) -> Vec<String> {
     let file = File::open(path);
@@ -72,260 +115,604 @@ pub fn split_file_lines(path: impl AsRef<Path>) -> Vec<String> {
         }
         Err(err) => {
             match err.kind() {
-                ErrorKind::NotFound => eprintln!("yui: File not found"),
+                ErrorKind::NotFound => {
+                    eprintln!("yui: File not found");
+                    exit(127);
+                }
                 ErrorKind::PermissionDenied => eprintln!("yui: Permission denied"),
                 _ => eprintln!("yui: Error reading file: {}", err),
             }
-            exit(1);
+            exit(126);
         }
     }
 }
 
-pub enum ArgTypes {
-    // first field is for the command, second indicates prescence of operator
-    Piped(Vec<String>, bool),
-    //Redir
-    Norm(Vec<String>, bool),
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    // a token that can't appear where it was found
+    Unexpected(String, Span),
+    // the input ended before the construct was finished, more lines may fix it
+    Incomplete(&'static str),
 }
 
-pub fn split_to_args(line: String) -> ArgTypes {
-    let mut args = Vec::new();
-    let mut cur_quot = String::new(); // for tracking the current quoted string
-    let mut cur_arg = String::new(); // for tracking the current arg
-    let mut has_pipe = false;
-    let mut has_op = false;
-    let mut prev_space = false;
-    let mut prev_spchar: char = '_'; // track the previous special character for two character combos, the '_' is just a placeholder
-    let mut new_cycle = false;
-
-    // TODO: Is just looping over all the characters really the best way to do this?
-    for c in line.trim_end().chars() {
-        // Order matters here!
-
-        // Single and double quotes
-        if c == '"' || c == '\'' {
-            if cur_quot.is_empty() {
-                cur_quot.push(c);
-                continue;
-            } else if cur_quot == c.to_string() {
-                cur_quot = String::new();
-                continue;
+impl ParseError {
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self, ParseError::Incomplete(_))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Unexpected(tok, span) => {
+                write!(f, "syntax error near unexpected token `{}' (column {})", tok, span.start + 1)
             }
+            ParseError::Incomplete(what) => write!(f, "syntax error: {}", what),
         }
+    }
+}
 
-        // Pipes
-        if c == '|' {
-            if cur_quot.is_empty() {
-                has_pipe = true;
-                args.push("|".to_string());
-                continue;
-            } else {
-                cur_arg.push(c);
-                continue;
-            }
+// Parse a line (or several) into a list, aliases are expanded in command position if given
+pub fn parse(input: &str, aliases: Option<&HashMap<String, String>>) -> Result<List, ParseError> {
+    let tokens = lexer::tokenize(input)?;
+    let mut parser = Parser { tokens, pos: 0, aliases, spliced: Vec::new() };
+    let list = parser.list()?;
+    match parser.peek() {
+        Some(tok) => Err(ParseError::Unexpected(tok.kind.to_string(), tok.span)),
+        None => Ok(list),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    aliases: Option<&'a HashMap<String, String>>,
+    spliced: Vec<(Range<usize>, String)>, // tokens that came from an alias, which isn't expanded again in them
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_op(&self) -> Option<Op> {
+        match self.peek() {
+            Some(Token { kind: TokenKind::Op(op), .. }) => Some(*op),
+            _ => None,
         }
+    }
 
-        // Expand home
-        if c == '~' && prev_space == true {
-            if cur_quot.is_empty() {
-                cur_arg.push_str(&paths::get_user_home());
-                continue;
-            } else {
-                cur_arg.push('~');
-                continue;
-            }
+    fn bump(&mut self) -> Option<Token> {
+        let tok = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        tok
+    }
+
+    fn skip_newlines(&mut self) {
+        while let Some(Token { kind: TokenKind::Newline, .. }) = self.peek() {
+            self.pos += 1;
         }
+    }
 
-        // Spaces
-        if c == ' ' {
-            if cur_quot.is_empty() {
-                if prev_space == false {
-                    if !cur_arg.trim().is_empty() {
-                        args.push(cur_arg.trim().to_string());
-                    }
-                    new_cycle = true;
-                    prev_space = true;
-                } else {
-                    continue;
-                }
-            } else {
-                cur_arg.push(c);
-                continue;
-            }
-        } else {
-            prev_space = false;
+    // error for the current token, or for the end of input
+    fn unexpected(&self, eof_msg: &'static str) -> ParseError {
+        match self.peek() {
+            Some(tok) => ParseError::Unexpected(tok.kind.to_string(), tok.span),
+            None => ParseError::Incomplete(eof_msg),
+        }
+    }
+
+    fn op_at(&self, n: usize) -> Option<Op> {
+        match self.tokens.get(self.pos + n) {
+            Some(Token { kind: TokenKind::Op(op), .. }) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn peek_word(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token { kind: TokenKind::Word(w), .. }) => Some(w),
+            _ => None,
         }
+    }
 
-        // && Operator
-        if c == '&' {
-            if cur_quot.is_empty() {
-                if prev_spchar == '&' {
-                    if !cur_arg.trim().is_empty() {
-                        args.push(cur_arg.trim().to_string());
+    // the words that end a list inside a compound command aren't commands
+    fn at_command_start(&self) -> bool {
+        match self.peek() {
+            Some(Token { kind: TokenKind::Word(w), .. }) => !is_terminator(w),
+            Some(Token { kind: TokenKind::IoNumber(_), .. }) | Some(Token { kind: TokenKind::Arith(_), .. }) => true,
+            Some(Token { kind: TokenKind::Op(op), .. }) => *op == Op::LParen || redirect_kind(*op).is_some(),
+            _ => false,
+        }
+    }
+
+    // list := and_or ((';' | '&' | newline) and_or)* [';' | '&']
+    fn list(&mut self) -> Result<List, ParseError> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if !self.at_command_start() {
+                break;
+            }
+            let and_or = self.and_or()?;
+            let background = match self.peek_op() {
+                Some(Op::Amp) => {
+                    self.bump();
+                    true
+                }
+                Some(Op::Semi) => {
+                    self.bump();
+                    false
+                }
+                _ => match self.peek() {
+                    Some(Token { kind: TokenKind::Newline, .. }) | None => false,
+                    Some(_) => {
+                        items.push(ListItem { and_or, background: false });
+                        break;
                     }
-                    //cur_arg.push_str("&&");
-                    args.push("&&".to_string());
-                    prev_spchar = ' ';
-                    has_op = true;
-                    new_cycle = true;
-                } else {
-                    prev_spchar = '&';
-                    continue;
+                },
+            };
+            items.push(ListItem { and_or, background });
+        }
+        Ok(List { items })
+    }
+
+    // and_or := pipeline (('&&' | '||') newline* pipeline)*
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.peek_op() {
+                Some(Op::AndIf) => Connector::And,
+                Some(Op::OrIf) => Connector::Or,
+                _ => break,
+            };
+            self.bump();
+            self.skip_newlines();
+            rest.push((connector, self.pipeline()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    // pipeline := command ('|' newline* command)*
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut commands = vec![self.command()?];
+        while self.peek_op() == Some(Op::Pipe) {
+            self.bump();
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline { commands })
+    }
+
+    // a list inside a compound command, it can't be empty
+    fn compound_list(&mut self) -> Result<List, ParseError> {
+        let list = self.list()?;
+        if list.items.is_empty() {
+            return Err(self.unexpected("expected a command"));
+        }
+        Ok(list)
+    }
+
+    // consume the reserved word expected next
+    fn reserved(&mut self, word: &str, eof_msg: &'static str) -> Result<(), ParseError> {
+        if self.peek_word() != Some(word) {
+            return Err(self.unexpected(eof_msg));
+        }
+        self.bump();
+        Ok(())
+    }
+
+    // command := simple_command | compound_command redirect* | function_def
+    // compound_command := if_clause | arith | '(' list ')' | '{' list '}'
+    fn command(&mut self) -> Result<Command, ParseError> {
+        let compound = if let Some(Token { kind: TokenKind::Arith(expr), .. }) = self.peek() {
+            let expr = expr.clone();
+            self.bump();
+            CompoundCommand::Arith(expr)
+        } else if self.peek_op() == Some(Op::LParen) {
+            self.bump();
+            let list = self.compound_list()?;
+            if self.peek_op() != Some(Op::RParen) {
+                return Err(self.unexpected("expected `)'"));
+            }
+            self.bump();
+            CompoundCommand::Subshell(list)
+        } else {
+            match self.peek_word() {
+                Some("if") => {
+                    self.bump();
+                    CompoundCommand::If(self.if_clause()?)
                 }
-            } else {
-                prev_spchar = ' ';
-                cur_arg.push(c);
-                continue;
+                Some("{") => {
+                    self.bump();
+                    let list = self.compound_list()?;
+                    self.reserved("}", "expected `}'")?;
+                    CompoundCommand::Group(list)
+                }
+                Some(w)
+                    if is_function_name(w)
+                        && self.op_at(1) == Some(Op::LParen)
+                        && self.op_at(2) == Some(Op::RParen) =>
+                {
+                    return Ok(Command::Function(self.function_def()?));
+                }
+                _ => return Ok(Command::Simple(self.simple_command()?)),
+            }
+        };
+        let mut redirects = Vec::new();
+        loop {
+            match self.peek().map(|t| t.kind.clone()) {
+                Some(TokenKind::IoNumber(n)) => {
+                    self.bump();
+                    redirects.push(self.redirect(Some(n))?);
+                }
+                Some(TokenKind::Op(op)) if redirect_kind(op).is_some() => {
+                    redirects.push(self.redirect(None)?);
+                }
+                _ => break,
             }
         }
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    // function_def := name '(' ')' newline* '{' list '}'
+    fn function_def(&mut self) -> Result<FunctionDef, ParseError> {
+        let name = self.bump().map(|t| t.kind.to_string()).unwrap_or_default();
+        self.pos += 2; // the parentheses
+        self.skip_newlines();
+        self.reserved("{", "expected `{'")?;
+        let body = self.compound_list()?;
+        self.reserved("}", "expected `}'")?;
+        Ok(FunctionDef { name, body })
+    }
 
-        // ; Operator
-        if c == ';' {
-            if cur_quot.is_empty() {
-                if !cur_arg.trim().is_empty() {
-                    args.push(cur_arg.trim().to_string());
+    // if_clause := 'if' list 'then' list ('elif' list 'then' list)* ['else' list] 'fi'
+    fn if_clause(&mut self) -> Result<IfClause, ParseError> {
+        let mut clause = IfClause { branches: Vec::new(), otherwise: None };
+        loop {
+            let cond = self.compound_list()?;
+            self.reserved("then", "expected `then'")?;
+            let body = self.compound_list()?;
+            clause.branches.push((cond, body));
+            match self.peek_word() {
+                Some("elif") => {
+                    self.bump();
+                }
+                Some("else") => {
+                    self.bump();
+                    clause.otherwise = Some(self.compound_list()?);
+                    self.reserved("fi", "expected `fi'")?;
+                    return Ok(clause);
+                }
+                _ => {
+                    self.reserved("fi", "expected `fi'")?;
+                    return Ok(clause);
                 }
-                //cur_arg.push_str(";");
-                args.push(";".to_string());
-                has_op = true;
-                new_cycle = true;
-            } else {
-                cur_arg.push(c);
-                continue;
             }
         }
+    }
 
-        // !! history expansion NOTE: WIP
-        /*if c == '!' {
-            if cur_quot.is_empty() {
-                if prev_spchar == '!' {
-                    cur_arg.push_str(History::last().unwrap());
-                    prev_spchar = ' ';
-                } else {
-                    prev_spchar = '!';
+    // simple_command := (assignment | redirect)* (word | redirect)*, not empty
+    fn simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut cmd = SimpleCommand::default();
+        loop {
+            match self.peek().map(|t| t.kind.clone()) {
+                Some(TokenKind::Word(w)) => {
+                    if cmd.words.is_empty() {
+                        if let Some((name, value)) = split_assignment(&w) {
+                            self.bump();
+                            cmd.assignments.push((name.to_string(), value.to_string()));
+                            continue;
+                        }
+                        if self.expand_alias(&w)? {
+                            continue;
+                        }
+                    }
+                    self.bump();
+                    cmd.words.push(w);
                 }
-            } else {
-                cur_arg.push('!');
+                Some(TokenKind::IoNumber(n)) => {
+                    self.bump();
+                    cmd.redirects.push(self.redirect(Some(n))?);
+                }
+                Some(TokenKind::Op(op)) if redirect_kind(op).is_some() => {
+                    cmd.redirects.push(self.redirect(None)?);
+                }
+                _ => break,
             }
-        }*/
+        }
+        if cmd.words.is_empty() && cmd.redirects.is_empty() && cmd.assignments.is_empty() {
+            return Err(self.unexpected("expected a command"));
+        }
+        Ok(cmd)
+    }
 
-        if new_cycle == true {
-            cur_arg = String::new();
-            new_cycle = false;
-            continue;
+    // Replace the current word with the tokens of its alias, returns false if it isn't one
+    fn expand_alias(&mut self, word: &str) -> Result<bool, ParseError> {
+        let pos = self.pos;
+        let recursive = self.spliced.iter().any(|(range, name)| range.contains(&pos) && name == word);
+        let value = match self.aliases.and_then(|a| a.get(word)) {
+            Some(v) if !recursive => v,
+            _ => return Ok(false),
+        };
+        let span = self.tokens[pos].span;
+        let mut replacement = lexer::tokenize(value)?;
+        for tok in replacement.iter_mut() {
+            tok.span = span; // point errors at the alias name
         }
+        let len = replacement.len();
+        self.tokens.splice(pos..pos + 1, replacement);
+        // the word is replaced by len tokens, moving the ones after it
+        for (range, _) in self.spliced.iter_mut() {
+            if range.start > pos {
+                range.start = range.start + len - 1;
+            }
+            if range.end > pos {
+                range.end = range.end + len - 1;
+            }
+        }
+        self.spliced.push((pos..pos + len, word.to_string()));
+        Ok(true)
+    }
 
-        // Regular character if it matches none of the above
-        cur_arg.push(c);
+    // redirect := [io_number] redir_op word
+    fn redirect(&mut self, fd: Option<i32>) -> Result<Redirect, ParseError> {
+        let kind = match self.peek_op().and_then(redirect_kind) {
+            Some(k) => k,
+            None => return Err(self.unexpected("expected a redirection")),
+        };
+        self.bump();
+        match self.peek().map(|t| t.kind.clone()) {
+            Some(TokenKind::HereDoc { body, quoted }) => {
+                self.bump();
+                Ok(Redirect { fd, kind: RedirectKind::HereDoc { expand: !quoted }, target: body })
+            }
+            Some(TokenKind::Word(target)) => {
+                self.bump();
+                Ok(Redirect { fd, kind, target })
+            }
+            _ => Err(self.unexpected("expected a file name after redirection")),
+        }
     }
+}
+
+// Reserved words that close or continue a compound command
+fn is_terminator(word: &str) -> bool {
+    matches!(word, "then" | "elif" | "else" | "fi" | "}")
+}
 
-    args.push(cur_arg.trim().to_string());
+pub fn is_reserved(word: &str) -> bool {
+    is_terminator(word) || matches!(word, "if" | "{")
+}
 
-    // Once again, the order is vitally important here
-    if has_pipe == true && has_op == true {
-        // both pipes and operators
-        ArgTypes::Piped(args, true)
-    } else if has_op == true {
-        // one or more operators
-        ArgTypes::Norm(args, true)
-    } else if has_pipe == true {
-        // one or more pipes
-        ArgTypes::Piped(args, false)
-    } else {
-        // normal command
-        ArgTypes::Norm(args, false)
-    }
+// Function names can have dashes and dots too, but nothing that would need quoting
+fn is_function_name(word: &str) -> bool {
+    !word.is_empty()
+        && !is_reserved(word)
+        && !word.starts_with('-')
+        && word.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
 }
 
-// split vector by '|'
-pub fn split_pipes(all: &[String]) -> Vec<&[String]> {
-    let mut vec = Vec::new();
-    let iter = all.split(|f| f == &"|");
-    vec.extend(iter);
-    return vec;
+// A NAME=value word, the name can't be quoted so the raw word can be split as is
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    let eq = word.find('=')?;
+    if expand::is_name(&word[..eq]) {
+        Some((&word[..eq], &word[eq + 1..]))
+    } else {
+        None
+    }
 }
 
-// split operators
-pub fn split_ops(all: &[String]) -> Vec<&[String]> {
-    let mut vec = Vec::new();
-    let iter = all.split(|f| f == "&&" || f == ";");
-    vec.extend(iter);
-    return vec;
+fn redirect_kind(op: Op) -> Option<RedirectKind> {
+    match op {
+        Op::Less => Some(RedirectKind::Input),
+        // the body replaces the delimiter token, see redirect()
+        Op::DLess | Op::DLessDash => Some(RedirectKind::HereDoc { expand: true }),
+        Op::TLess => Some(RedirectKind::HereString),
+        Op::Great => Some(RedirectKind::Output),
+        Op::DGreat => Some(RedirectKind::Append),
+        Op::LessAnd => Some(RedirectKind::DupInput),
+        Op::GreatAnd => Some(RedirectKind::DupOutput),
+        Op::AndGreat => Some(RedirectKind::OutputBoth),
+        Op::AndDGreat => Some(RedirectKind::AppendBoth),
+        _ => None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn simple(words: &[&str]) -> Command {
+        Command::Simple(SimpleCommand { words: words.iter().map(|w| w.to_string()).collect(), ..Default::default() })
+    }
+
+    fn as_simple(cmd: &Command) -> &SimpleCommand {
+        match cmd {
+            Command::Simple(simple) => simple,
+            _ => panic!("not a simple command: {:?}", cmd),
+        }
+    }
+
+    fn pipeline(cmds: &[&[&str]]) -> Pipeline {
+        Pipeline { commands: cmds.iter().map(|c| simple(c)).collect() }
+    }
+
     #[test]
-    fn test_split_ops() {
+    fn test_parse_simple() {
+        let list = parse("ls -al", None).unwrap();
+        assert_eq!(list.items.len(), 1);
+        assert_eq!(list.items[0].and_or.first, pipeline(&[&["ls", "-al"]]));
+        assert_eq!(parse("   ", None).unwrap(), List::default());
         assert_eq!(
-            split_ops(&vec!["ls".to_string(), "&&".to_string(), "echo".to_string()]),
-            vec![vec!["ls".to_string()], vec!["echo".to_string()]]
-        );
-        assert_eq!(
-            split_ops(&vec![
-                "ls".to_string(),
-                "-al".to_string(),
-                "&&".to_string(),
-                "echo".to_string(),
-                "hello space".to_string()
-            ]),
-            vec![vec!["ls".to_string(), "-al".to_string()], vec!["echo".to_string(), "hello space".to_string()]]
-        );
-        assert_eq!(
-            split_ops(&vec!["ls".to_string(), "-al".to_string(), ";".to_string(), "ls".to_string()]),
-            vec![vec!["ls".to_string(), "-al".to_string()], vec!["ls".to_string()]]
+            parse("echo 'hello space'", None).unwrap().items[0].and_or.first,
+            pipeline(&[&["echo", "'hello space'"]])
         );
+    }
+
+    #[test]
+    fn test_parse_pipes_and_ops() {
+        let list = parse("make | tee log && notify-send done", None).unwrap();
+        let and_or = &list.items[0].and_or;
+        assert_eq!(and_or.first, pipeline(&[&["make"], &["tee", "log"]]));
+        assert_eq!(and_or.rest, vec![(Connector::And, pipeline(&[&["notify-send", "done"]]))]);
+
+        let list = parse("ls -al; false || echo a | wc\nls", None).unwrap();
+        assert_eq!(list.items.len(), 3);
+        assert_eq!(list.items[1].and_or.rest[0].0, Connector::Or);
+        assert_eq!(list.items[1].and_or.rest[0].1, pipeline(&[&["echo", "a"], &["wc"]]));
+        assert!(parse("sleep 1 &", None).unwrap().items[0].background);
+    }
+
+    #[test]
+    fn test_parse_redirects() {
+        let list = parse("cmd <in 2>&1 >>out", None).unwrap();
+        let cmd = as_simple(&list.items[0].and_or.first.commands[0]);
+        assert_eq!(cmd.words, vec!["cmd".to_string()]);
         assert_eq!(
-            split_ops(&vec![
-                "ls".to_string(),
-                "-al".to_string(),
-                ";".to_string(),
-                "ls".to_string(),
-                "&&".to_string(),
-                "ls".to_string()
-            ]),
-            vec![vec!["ls".to_string(), "-al".to_string()], vec!["ls".to_string()], vec!["ls".to_string()]]
+            cmd.redirects,
+            vec![
+                Redirect { fd: None, kind: RedirectKind::Input, target: "in".to_string() },
+                Redirect { fd: Some(2), kind: RedirectKind::DupOutput, target: "1".to_string() },
+                Redirect { fd: None, kind: RedirectKind::Append, target: "out".to_string() },
+            ]
         );
     }
 
     #[test]
-    fn test_split_pipes() {
+    fn test_parse_assignments() {
+        let list = parse("A=1 B='x y' cmd C=2 | D=3 ls", None).unwrap();
+        let cmds = &list.items[0].and_or.first.commands;
+        let cmd = as_simple(&cmds[0]);
+        assert_eq!(cmd.assignments, vec![("A".to_string(), "1".to_string()), ("B".to_string(), "'x y'".to_string())]);
+        assert_eq!(cmd.words, vec!["cmd".to_string(), "C=2".to_string()]);
+        let cmd = as_simple(&cmds[1]);
+        assert_eq!(cmd.assignments, vec![("D".to_string(), "3".to_string())]);
+
+        let list = parse("X= ; 1A=2 '=x'", None).unwrap();
+        let cmd = as_simple(&list.items[0].and_or.first.commands[0]);
+        assert_eq!(cmd.assignments, vec![("X".to_string(), "".to_string())]);
+        assert!(cmd.words.is_empty());
+        let cmd = as_simple(&list.items[1].and_or.first.commands[0]);
+        assert!(cmd.assignments.is_empty());
+    }
+
+    #[test]
+    fn test_parse_if() {
+        let list = parse("if a; then b; elif c\nthen d\nelse e; fi | wc", None).unwrap();
+        let cmds = &list.items[0].and_or.first.commands;
+        assert_eq!(cmds.len(), 2);
+        let clause = match &cmds[0] {
+            Command::Compound(CompoundCommand::If(clause), _) => clause,
+            cmd => panic!("not an if: {:?}", cmd),
+        };
+        assert_eq!(clause.branches.len(), 2);
+        assert_eq!(clause.branches[1].1.items[0].and_or.first, pipeline(&[&["d"]]));
+        assert_eq!(clause.otherwise.as_ref().unwrap().items[0].and_or.first, pipeline(&[&["e"]]));
+        assert_eq!(cmds[0].to_string(), "if a; then b; elif c; then d; else e; fi");
+
+        // reserved words only count in command position
+        assert_eq!(parse("echo if fi", None).unwrap().items[0].and_or.first, pipeline(&[&["echo", "if", "fi"]]));
+        assert!(parse("if true; then\necho", None).unwrap_err().is_incomplete());
+        assert!(parse("if true", None).unwrap_err().is_incomplete());
+        assert!(matches!(parse("if true; then fi", None), Err(ParseError::Unexpected(_, _))));
+        assert!(matches!(parse("fi", None), Err(ParseError::Unexpected(_, _))));
+    }
+
+    #[test]
+    fn test_parse_groups() {
+        let list = parse("( cd build && make ) | tee log; { a; b\n} >out 2>&1 || c", None).unwrap();
+        let cmds = &list.items[0].and_or.first.commands;
+        assert_eq!(cmds.len(), 2);
+        match &cmds[0] {
+            Command::Compound(CompoundCommand::Subshell(body), redirects) => {
+                assert_eq!(body.items[0].and_or.rest[0].1, pipeline(&[&["make"]]));
+                assert!(redirects.is_empty());
+            }
+            cmd => panic!("not a subshell: {:?}", cmd),
+        }
+        let and_or = &list.items[1].and_or;
+        match &and_or.first.commands[0] {
+            Command::Compound(CompoundCommand::Group(body), redirects) => {
+                assert_eq!(body.items.len(), 2);
+                assert_eq!(redirects.len(), 2);
+            }
+            cmd => panic!("not a group: {:?}", cmd),
+        }
+        assert_eq!(and_or.to_string(), "{ a; b; } >out 2>&1 || c");
+        assert_eq!(cmds[0].to_string(), "( cd build && make; )");
+
+        // nested subshells need a space so they aren't arithmetic
+        assert!(parse("( (echo) )", None).is_ok());
+        assert!(parse("(echo", None).unwrap_err().is_incomplete());
+        assert!(parse("{ echo; ", None).unwrap_err().is_incomplete());
+        assert!(matches!(parse("( )", None), Err(ParseError::Unexpected(_, _))));
+        assert!(matches!(parse("(echo) x", None), Err(ParseError::Unexpected(_, _))));
+        assert_eq!(parse("echo }", None).unwrap().items[0].and_or.first, pipeline(&[&["echo", "}"]]));
+    }
+
+    #[test]
+    fn test_parse_functions() {
+        let list = parse("greet() { echo hi $1; }; my-fn ()\n{\n  greet x\n  false\n}", None).unwrap();
+        assert_eq!(list.items.len(), 2);
+        let def = match &list.items[1].and_or.first.commands[0] {
+            Command::Function(def) => def,
+            cmd => panic!("not a function: {:?}", cmd),
+        };
+        assert_eq!(def.name, "my-fn");
+        assert_eq!(def.body.items.len(), 2);
+        assert_eq!(list.items[0].and_or.first.commands[0].to_string(), "greet() { echo hi $1; }");
+
+        assert!(parse("f() {", None).unwrap_err().is_incomplete());
+        assert!(parse("f() { echo }", None).unwrap_err().is_incomplete());
+        assert!(matches!(parse("f() echo", None), Err(ParseError::Unexpected(_, _))));
+        assert!(matches!(parse("f() { }", None), Err(ParseError::Unexpected(_, _))));
+    }
+
+    #[test]
+    fn test_parse_heredoc() {
+        let list = parse("cat <<'EOF' | wc -l\n$HOME\nEOF\necho after", None).unwrap();
+        assert_eq!(list.items.len(), 2);
+        let cmd = as_simple(&list.items[0].and_or.first.commands[0]);
         assert_eq!(
-            split_pipes(&vec!["ls".to_string(), "|".to_string(), "echo".to_string()]),
-            vec![vec!["ls".to_string()], vec!["echo".to_string()]]
+            cmd.redirects,
+            vec![Redirect { fd: None, kind: RedirectKind::HereDoc { expand: false }, target: "$HOME\n".to_string() }]
         );
+        assert!(parse("cat <<EOF\nno end", None).unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn test_parse_aliases() {
+        let mut aliases = HashMap::new();
+        aliases.insert("ls".to_string(), "ls --color=auto".to_string());
+        aliases.insert("lsl".to_string(), "ls -l | less".to_string());
+        let list = parse("ls x | lsl", Some(&aliases)).unwrap();
         assert_eq!(
-            split_pipes(&vec![
-                "ls".to_string(),
-                "-al".to_string(),
-                "|".to_string(),
-                "echo".to_string(),
-                "hello space".to_string(),
-                "|".to_string(),
-                "echo".to_string()
-            ]),
-            vec![
-                vec!["ls".to_string(), "-al".to_string()],
-                vec!["echo".to_string(), "hello space".to_string()],
-                vec!["echo".to_string()]
-            ]
+            list.items[0].and_or.first,
+            pipeline(&[&["ls", "--color=auto", "x"], &["ls", "--color=auto", "-l"], &["less"]])
         );
+        // only in command position
+        let list = parse("echo ls", Some(&aliases)).unwrap();
+        assert_eq!(list.items[0].and_or.first, pipeline(&[&["echo", "ls"]]));
+
+        // an alias that runs itself after a separator isn't expanded again
+        aliases.insert("ls".to_string(), "echo x; ls".to_string());
+        let list = parse("ls; ls", Some(&aliases)).unwrap();
+        let firsts: Vec<&Pipeline> = list.items.iter().map(|i| &i.and_or.first).collect();
+        let (echo, ls) = (pipeline(&[&["echo", "x"]]), pipeline(&[&["ls"]]));
+        assert_eq!(firsts, vec![&echo, &ls, &echo, &ls]);
+        aliases.insert("a".to_string(), "b && a".to_string());
+        aliases.insert("b".to_string(), "a | b".to_string());
+        assert!(parse("a", Some(&aliases)).is_ok());
     }
 
-    /*#[test]
-    fn test_split_line_to_args() {
-        assert_eq!(split_to_args("ls".to_string()), vec!["ls"]);
-        assert_eq!(split_to_args(" ls".to_string()), vec!["", "ls"]);
-        assert_eq!(split_to_args(" ls   ".to_string()), vec!["", "ls"]);
-        assert_eq!(split_to_args("   ls   ".to_string()), vec!["", "ls"]);
-        assert_eq!(split_to_args("   ls  -a     -l  ".to_string()), vec!["", "ls", "-a", "-l"]);
-        assert_eq!(split_to_args("ls -al".to_string()), vec!["ls", "-al"]);
-        assert_eq!(split_to_args("ls -a -l".to_string()), vec!["ls", "-a", "-l"]);
-        assert_eq!(split_to_args("ls 'single quotes'".to_string()), vec!["ls", "single quotes"]);
-        assert_eq!(split_to_args("ls \"double quotes\"".to_string()), vec!["ls", "double quotes"]);
-        assert_eq!(split_to_args("ls | wc".to_string()), vec!["ls", "|", "wc"]);
-        assert_eq!(split_to_args("echo pipes with args | wc".to_string()), vec!["echo", "pipes", "with", "args", "|", "wc"]);
-    }*/
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("ls | | wc", None), Err(ParseError::Unexpected("|".to_string(), Span::new(5, 6))));
+        assert!(matches!(parse("; ls", None), Err(ParseError::Unexpected(_, _))));
+        assert!(parse("ls &&", None).unwrap_err().is_incomplete());
+        assert!(parse("ls |", None).unwrap_err().is_incomplete());
+        assert!(parse("echo >", None).unwrap_err().is_incomplete());
+    }
 }
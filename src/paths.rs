@@ -61,6 +61,17 @@ pub fn condense_home(text: &str) -> String {
     text.replace(&get_user_home(), "~")
 }
 
+// Where a command would be run from, searching path (a $PATH value) if it has no slash
+pub fn find_command(cmd: &str, path: &str) -> Option<String> {
+    let runnable = |p: &str| Path::new(p).is_file() && crate::sys::access(p, libc::X_OK);
+    if cmd.contains('/') {
+        return Some(cmd.to_string()).filter(|c| runnable(c));
+    }
+    path.split(':')
+        .map(|dir| if dir.is_empty() { cmd.to_string() } else { format!("{}/{}", dir.trim_end_matches('/'), cmd) })
+        .find(|p| runnable(p))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -72,4 +83,12 @@ mod tests {
         assert_eq!(expand_home("~"), "~".to_string());
         assert_eq!(expand_home("echo ~"), ["echo".to_string(), get_user_home()].join(" "));
     }
+
+    #[test]
+    fn find_command_test() {
+        assert_eq!(find_command("sh", "/nonexistent:/bin"), Some("/bin/sh".to_string()));
+        assert_eq!(find_command("sh", "/nonexistent"), None);
+        assert_eq!(find_command("/bin/sh", ""), Some("/bin/sh".to_string()));
+        assert_eq!(find_command("/etc", "/bin"), None);
+    }
 }
@@ -0,0 +1,256 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::ast::{Redirect, RedirectKind};
+use crate::context::Context;
+use crate::expand;
+use crate::sys;
+
+// A single fd change, applied in order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FdOp {
+    Dup(RawFd, RawFd), // dup2(src, dst)
+    Close(RawFd),
+}
+
+// Redirections of a command, with the files they opened kept alive until the command is done
+#[derive(Default)]
+pub struct Redirections {
+    pub ops: Vec<FdOp>,
+    files: Vec<File>,
+}
+
+impl Redirections {
+    // Opens all target files, the error message is ready to be printed
+    pub fn resolve(ctx: &mut Context, redirects: &[Redirect]) -> Result<Self, String> {
+        let mut r = Redirections::default();
+        for redir in redirects.iter() {
+            if let RedirectKind::HereDoc { expand } = redir.kind {
+                let body = if expand {
+                    expand::expand_heredoc(ctx, &redir.target).map_err(|e| e.to_string())?
+                } else {
+                    redir.target.clone()
+                };
+                let f = r.here(&body)?;
+                r.ops.push(FdOp::Dup(f, redir.fd.unwrap_or(0)));
+                continue;
+            }
+            let target = expand::expand_word(ctx, &redir.target).map_err(|e| e.to_string())?;
+            match redir.kind {
+                RedirectKind::Input => {
+                    let f = r.open(&target, OpenOptions::new().read(true))?;
+                    r.ops.push(FdOp::Dup(f, redir.fd.unwrap_or(0)));
+                }
+                RedirectKind::HereDoc { .. } => unreachable!(),
+                RedirectKind::HereString => {
+                    let f = r.here(&(target + "\n"))?;
+                    r.ops.push(FdOp::Dup(f, redir.fd.unwrap_or(0)));
+                }
+                RedirectKind::Output => {
+                    let f = r.open(&target, OpenOptions::new().write(true).create(true).truncate(true))?;
+                    r.ops.push(FdOp::Dup(f, redir.fd.unwrap_or(1)));
+                }
+                RedirectKind::Append => {
+                    let f = r.open(&target, OpenOptions::new().append(true).create(true))?;
+                    r.ops.push(FdOp::Dup(f, redir.fd.unwrap_or(1)));
+                }
+                RedirectKind::DupInput | RedirectKind::DupOutput => {
+                    let input = redir.kind == RedirectKind::DupInput;
+                    let dst = redir.fd.unwrap_or(if input { 0 } else { 1 });
+                    if target == "-" {
+                        r.ops.push(FdOp::Close(dst));
+                    } else if let Ok(src) = target.parse::<RawFd>() {
+                        r.ops.push(FdOp::Dup(src, dst));
+                    } else if !input && redir.fd.is_none() {
+                        // >&file is the same as &>file
+                        let f = r.open(&target, OpenOptions::new().write(true).create(true).truncate(true))?;
+                        r.ops.push(FdOp::Dup(f, 1));
+                        r.ops.push(FdOp::Dup(f, 2));
+                    } else {
+                        return Err(format!("{}: ambiguous redirect", target));
+                    }
+                }
+                RedirectKind::OutputBoth | RedirectKind::AppendBoth => {
+                    let f = if redir.kind == RedirectKind::OutputBoth {
+                        r.open(&target, OpenOptions::new().write(true).create(true).truncate(true))?
+                    } else {
+                        r.open(&target, OpenOptions::new().append(true).create(true))?
+                    };
+                    r.ops.push(FdOp::Dup(f, 1));
+                    r.ops.push(FdOp::Dup(f, 2));
+                }
+            }
+        }
+        Ok(r)
+    }
+
+    fn open(&mut self, path: &str, opts: &OpenOptions) -> Result<RawFd, String> {
+        opts.open(path).and_then(|f| self.keep(f)).map_err(|e| format!("{}: {}", path, io_error_msg(&e)))
+    }
+
+    // Put text for a heredoc in an already unlinked temp file, so it can't block like a full pipe
+    fn here(&mut self, text: &str) -> Result<RawFd, String> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("yui-heredoc-{}-{}", process::id(), n));
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| format!("here-document: {}", io_error_msg(&e)))?;
+        let _ = fs::remove_file(&path);
+        f.write_all(text.as_bytes())
+            .and_then(|_| f.seek(SeekFrom::Start(0)))
+            .map_err(|e| format!("here-document: {}", io_error_msg(&e)))?;
+        self.keep(f).map_err(|e| format!("here-document: {}", io_error_msg(&e)))
+    }
+
+    // Keep a file open until the command is done. It's moved up out of the way first, or it could
+    // land on an fd an earlier redirection targets and be clobbered when that one is applied.
+    fn keep(&mut self, f: File) -> io::Result<RawFd> {
+        let fd = sys::dup_high(f.as_raw_fd()).ok_or_else(io::Error::last_os_error)?;
+        self.files.push(unsafe { File::from_raw_fd(fd) });
+        Ok(fd)
+    }
+
+    // Apply to the current process, handing back what's needed to undo it
+    pub fn apply_saved(&self) -> io::Result<SavedFds> {
+        let mut saved = SavedFds(Vec::new());
+        for op in self.ops.iter() {
+            let fd = match op {
+                FdOp::Dup(_, dst) => *dst,
+                FdOp::Close(fd) => *fd,
+            };
+            if !saved.0.iter().any(|(f, _)| *f == fd) {
+                saved.0.push((fd, sys::dup_high(fd)));
+            }
+        }
+        flush_std();
+        if let Err(e) = apply(&self.ops) {
+            saved.restore();
+            return Err(e);
+        }
+        Ok(saved)
+    }
+}
+
+// Apply fd changes, only makes async-signal-safe calls so it can run between fork and exec
+pub fn apply(ops: &[FdOp]) -> io::Result<()> {
+    for op in ops.iter() {
+        match *op {
+            FdOp::Dup(src, dst) => sys::dup2(src, dst)?,
+            FdOp::Close(fd) => sys::close(fd),
+        }
+    }
+    Ok(())
+}
+
+// The fds a builtin's redirections replaced, and copies of what they were before
+pub struct SavedFds(Vec<(RawFd, Option<RawFd>)>);
+
+impl SavedFds {
+    pub fn restore(self) {
+        flush_std();
+        for (fd, saved) in self.0.into_iter().rev() {
+            match saved {
+                Some(s) => {
+                    let _ = sys::dup2(s, fd);
+                    sys::close(s);
+                }
+                None => sys::close(fd),
+            }
+        }
+    }
+}
+
+fn flush_std() {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+}
+
+// io::Error's Display adds "(os error N)", which we don't want to show
+pub fn io_error_msg(e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::NotFound => "No such file or directory".to_string(),
+        io::ErrorKind::PermissionDenied => "Permission denied".to_string(),
+        _ => {
+            let msg = e.to_string();
+            match msg.find(" (os error") {
+                Some(i) => msg[..i].to_string(),
+                None => msg,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redir(fd: Option<i32>, kind: RedirectKind, target: &str) -> Redirect {
+        Redirect { fd, kind, target: target.to_string() }
+    }
+
+    #[test]
+    fn test_resolve_dups() {
+        let r = Redirections::resolve(
+            &mut Context::new(),
+            &[
+                redir(Some(2), RedirectKind::DupOutput, "1"),
+                redir(None, RedirectKind::DupOutput, "2"),
+                redir(Some(3), RedirectKind::DupInput, "-"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(r.ops, vec![FdOp::Dup(1, 2), FdOp::Dup(2, 1), FdOp::Close(3)]);
+        assert!(Redirections::resolve(&mut Context::new(), &[redir(Some(2), RedirectKind::DupOutput, "file")]).is_err());
+    }
+
+    #[test]
+    fn test_resolve_files() {
+        let path = std::env::temp_dir().join(format!("yui-redir-test-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let r = Redirections::resolve(&mut Context::new(), &[redir(None, RedirectKind::OutputBoth, path)]).unwrap();
+        match r.ops[..] {
+            [FdOp::Dup(a, 1), FdOp::Dup(b, 2)] => assert_eq!(a, b),
+            _ => panic!("unexpected ops: {:?}", r.ops),
+        }
+        // opened above the fds being redirected, so applying one can't clobber another
+        let r = Redirections::resolve(
+            &mut Context::new(),
+            &[redir(Some(4), RedirectKind::Output, path), redir(Some(3), RedirectKind::Input, path)],
+        )
+        .unwrap();
+        match r.ops[..] {
+            [FdOp::Dup(a, 4), FdOp::Dup(b, 3)] => assert!(a >= 10 && b >= 10 && a != b),
+            _ => panic!("unexpected ops: {:?}", r.ops),
+        }
+        let err =
+            Redirections::resolve(&mut Context::new(), &[redir(None, RedirectKind::Input, "/nonexistent/file")]).err();
+        assert_eq!(err, Some("/nonexistent/file: No such file or directory".to_string()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_here() {
+        use std::io::Read;
+
+        let r = Redirections::resolve(&mut Context::new(), &[redir(None, RedirectKind::HereString, "'a b'")]).unwrap();
+        let fd = match r.ops[..] {
+            [FdOp::Dup(fd, 0)] => fd,
+            _ => panic!("unexpected ops: {:?}", r.ops),
+        };
+        // read through a dup so the file is only closed once
+        let mut f = unsafe { File::from_raw_fd(libc::dup(fd)) };
+        let mut text = String::new();
+        f.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "a b\n");
+    }
+}
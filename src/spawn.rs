@@ -1,172 +1,597 @@
-use std::io::{Read, Write};
-use std::process::{Command, Stdio};
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Read, Write},
+    mem,
+    os::unix::{io::AsRawFd, process::CommandExt},
+    process::{Command, Stdio},
+    rc::Rc,
+};
 
+use libc::pid_t;
+
+use crate::ast;
 use crate::builtins;
-use crate::context::Context;
+use crate::context::{Context, Frame, Var};
+use crate::expand;
+use crate::jobs::{self, Job, ProcessGroup};
 use crate::parser;
+use crate::redirect::{self, FdOp, Redirections};
+use crate::sys::{self, ProcStatus};
+
+// Parse a line and run it, reporting any syntax errors
+pub fn run_line(ctx: &mut Context, int: bool, line: &str) -> i32 {
+    // only expand aliases in interactive mode
+    let aliases = if int { Some(&ctx.aliases) } else { None };
+    match parser::parse(line, aliases) {
+        Ok(list) => run_list(ctx, &list),
+        Err(e) => {
+            eprintln!("yui: {}", e);
+            ctx.laststatus = 2;
+            2
+        }
+    }
+}
 
-pub fn choose_and_run(ctx: &mut Context, int: bool, raw: parser::ArgTypes) {
-    match raw {
-        parser::ArgTypes::Norm(data, false) => {
-            // normal command
-            spawn_cmd(ctx, int, &data);
+// Run every item of a list, returning the status of the last one
+pub fn run_list(ctx: &mut Context, list: &ast::List) -> i32 {
+    for item in list.items.iter() {
+        if ctx.stopping() {
+            break;
+        }
+        if item.background {
+            run_background(ctx, &item.and_or);
+        } else {
+            run_and_or(ctx, &item.and_or);
         }
-        parser::ArgTypes::Piped(data, false) => {
-            // contains one or more pipes
-            let parts = parser::split_pipes(&data);
-            spawn_piped(&parts);
+    }
+    ctx.laststatus
+}
+
+// Start an and-or list without waiting for it. A lone pipeline becomes the job itself, anything
+// longer runs in a forked copy of the shell.
+fn run_background(ctx: &mut Context, and_or: &ast::AndOr) {
+    ctx.laststatus = 0;
+    if and_or.rest.is_empty() {
+        run_pipeline(ctx, &and_or.first, true);
+        return;
+    }
+    let mut job = Job::new(and_or.to_string());
+    let group = ctx.jobs.group(0, false);
+    match sys::fork() {
+        Ok(0) => {
+            // child, never returns
+            if let Some(g) = group {
+                g.join();
+            }
+            jobs::reset_signals();
+            ctx.jobs.forked();
+            let status = run_and_or(ctx, and_or);
+            let _ = io::stdout().flush();
+            sys::exit_child(status);
         }
-        parser::ArgTypes::Norm(data, true) => {
-            // normal command with op
-            // TODO: make this proper once we get exit code handling
-            let parts = parser::split_ops(&data);
-            spawn_chained(&parts);
+        Ok(pid) => {
+            job.add_process(pid, ctx.jobs.control);
+            add_background(ctx, job);
         }
-        parser::ArgTypes::Piped(_data, true) => {
-            // contains one or more pipes with op in one of them
-            eprintln!("yui: Multiple operators with ambiguous precedence");
+        Err(e) => eprintln!("yui: fork: {}", e),
+    }
+}
+
+fn add_background(ctx: &mut Context, job: Job) {
+    let pid = match job.last_pid() {
+        Some(pid) => pid,
+        None => return, // nothing could be started
+    };
+    ctx.last_bg = Some(pid);
+    let id = ctx.jobs.add(job);
+    if ctx.jobs.control {
+        eprintln!("[{}] {}", id, pid);
+    }
+}
+
+// Run a list in a forked copy of the shell and collect its stdout, for $(...)
+pub fn capture(ctx: &mut Context, list: &ast::List) -> Result<(String, i32), String> {
+    let (mut read, write) = sys::pipe().map_err(|e| format!("pipe error: {}", e))?;
+    let pid = match sys::fork() {
+        Ok(0) => {
+            // child, never returns
+            drop(read);
+            jobs::reset_signals();
+            ctx.jobs.forked();
+            if let Err(e) = sys::dup2(write.as_raw_fd(), 1) {
+                eprintln!("yui: {}", e);
+                sys::exit_child(1);
+            }
+            drop(write);
+            let status = run_list(ctx, list);
+            let _ = io::stdout().flush();
+            sys::exit_child(status);
         }
+        Ok(pid) => pid,
+        Err(e) => return Err(format!("fork: {}", e)),
     };
+    drop(write);
+    let mut out = Vec::new();
+    if let Err(e) = read.read_to_end(&mut out) {
+        eprintln!("yui: command substitution: {}", e);
+    }
+    Ok((String::from_utf8_lossy(&out).into_owned(), wait_child(pid)))
 }
 
-pub fn spawn_cmd(ctx: &mut Context, int: bool, raw: &[String]) {
-    let mut cmd: Vec<String>;
-    if let Some(d) = check_aliases(ctx, &raw[0]) {
-        if int == true {
-            // only expand aliases in interactive mode
-            cmd = raw.to_vec();
-            let _ = cmd.splice(..1, d.iter().cloned());
-        } else {
-            cmd = raw.to_vec();
+// && only continues on success and || only on failure
+fn run_and_or(ctx: &mut Context, and_or: &ast::AndOr) -> i32 {
+    ctx.laststatus = run_pipeline(ctx, &and_or.first, false);
+    for (connector, pipeline) in and_or.rest.iter() {
+        if ctx.stopping() {
+            break;
+        }
+        let go_on = match connector {
+            ast::Connector::And => ctx.laststatus == 0,
+            ast::Connector::Or => ctx.laststatus != 0,
+        };
+        if go_on {
+            ctx.laststatus = run_pipeline(ctx, pipeline, false);
         }
-    } else {
-        cmd = raw.to_vec();
     }
-    let mut cmd_split = cmd.iter();
-    let cmd = cmd_split.next().unwrap(); // first one will be the command
-    let args = cmd_split.clone();
-    // check for builtins
-    if check_builtins(ctx, &mut cmd.as_str(), &cmd_split.collect::<Vec<&String>>()) {
-        return;
-    } else {
-        // Run commands, echo any errors
-        let child_cur = Command::new(cmd).args(args).spawn();
-        match child_cur {
-            Ok(mut child) => match child.wait() {
-                Ok(c) => match c.code() {
-                    Some(status) => ctx.laststatus = status,
-                    None => println!("terminated by signal"),
-                },
-                Err(e) => eprintln!("{}", e),
-            },
-            Err(e) => eprintln!("{}", e),
-        }
-    }
-}
-
-fn spawn_piped(all: &[&[String]]) {
-    let mut cmds = all.iter().peekable(); // peekable so we know when we are on the last cmd
-
-    // split off and spawn first cmd
-    let mut first_cmd = cmds.next().unwrap().iter();
-    let mut first_cmd_spawn =
-        Command::new(first_cmd.next().unwrap()).args(first_cmd).stdout(Stdio::piped()).spawn().unwrap();
-
-    // buffer for writing stdout into
-    let mut buf: Vec<u8> = Vec::new();
-    let mut store_stdout = first_cmd_spawn.stdout.take();
-
-    if let Err(e) = first_cmd_spawn.wait() {
-        eprintln!("yui: pipe error: {}", e);
-    }
-
-    while let Some(c) = cmds.next() {
-        let mut iter = c.iter();
-        if cmds.peek().is_some() {
-            let mut middle_cmd_spawn = Command::new(iter.next().unwrap())
-                .args(iter)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()
-                .unwrap();
-
-            // Read previous stdout into current stdin
-            if let Some(ref mut prev_stdout) = store_stdout {
-                if let Some(ref mut cur_stdin) = middle_cmd_spawn.stdin {
-                    prev_stdout.read_to_end(&mut buf).unwrap();
-                    cur_stdin.write_all(&buf).unwrap();
+    ctx.laststatus
+}
+
+// A pipeline stage ready to run, redirs is None if they couldn't be set up
+struct Stage<'a> {
+    env: Vec<(String, String)>, // assignments in front of the command, only for that command
+    words: Vec<String>,
+    redirs: Option<Redirections>,
+    compound: Option<&'a ast::CompoundCommand>, // run instead of words if set
+}
+
+fn run_pipeline(ctx: &mut Context, pipeline: &ast::Pipeline, background: bool) -> i32 {
+    let mut stages = Vec::new();
+    for cmd in pipeline.commands.iter() {
+        let simple = match cmd {
+            ast::Command::Simple(simple) => simple,
+            ast::Command::Compound(compound, redirects) => {
+                let redirs = match Redirections::resolve(ctx, redirects) {
+                    Ok(r) => Some(r),
+                    Err(e) => {
+                        eprintln!("yui: {}", e);
+                        None
+                    }
+                };
+                stages.push(Stage { env: Vec::new(), words: Vec::new(), redirs, compound: Some(compound) });
+                continue;
+            }
+            ast::Command::Function(def) => {
+                if pipeline.commands.len() == 1 && !background {
+                    ctx.functions.insert(def.name.clone(), Rc::new(def.clone()));
+                    return 0;
+                }
+                // anywhere else it's defined in a forked shell, where it's gone right away
+                let redirs = Some(Redirections::default());
+                stages.push(Stage { env: Vec::new(), words: Vec::new(), redirs, compound: None });
+                continue;
+            }
+        };
+        // a command of only assignments sets shell variables, one after the other so later ones
+        // can use earlier ones. It gets the status of its last command substitution.
+        let assign = simple.words.is_empty() && pipeline.commands.len() == 1 && !background;
+        if assign {
+            ctx.laststatus = 0;
+        }
+        let words = match expand::expand_words(ctx, &simple.words) {
+            Ok(w) => w,
+            Err(e) => {
+                eprintln!("yui: {}", e);
+                return 1;
+            }
+        };
+        let mut env = Vec::new();
+        for (name, value) in simple.assignments.iter() {
+            let set = match expand::expand_assignment(ctx, value) {
+                Ok(v) if assign => ctx.set_var(name, &v).map_err(|e| e.to_string()),
+                Ok(_) if ctx.is_readonly(name) => Err(format!("{}: readonly variable", name)),
+                Ok(v) => {
+                    env.push((name.clone(), v));
+                    Ok(())
                 }
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = set {
+                eprintln!("yui: {}", e);
+                return 1;
+            }
+        }
+        let redirs = match Redirections::resolve(ctx, &simple.redirects) {
+            Ok(r) => Some(r),
+            Err(e) => {
+                eprintln!("yui: {}", e);
+                None
             }
+        };
+        stages.push(Stage { env, words, redirs, compound: None });
+    }
+    // a lone builtin, function or compound command runs in the shell itself, so it can change the
+    // shell's state. Subshells are forked even then, so nothing they do leaks out.
+    let subshell = matches!(stages[0].compound, Some(ast::CompoundCommand::Subshell(_)));
+    if !background && stages.len() == 1 && !subshell {
+        let stage = &stages[0];
+        let in_shell = stage.compound.is_some() || stage.words.first().filter(|c| !runs_in_shell(ctx, c)).is_none();
+        if in_shell {
+            return match (&stage.redirs, stage.compound) {
+                (Some(redirs), Some(compound)) => run_redirected(ctx, compound, redirs),
+                (Some(redirs), None) => run_builtin(ctx, stage, redirs),
+                (None, _) => 1,
+            };
+        }
+    }
 
-            store_stdout = middle_cmd_spawn.stdout.take(); // overwrite the current stdout, which
-                                                           // will become the previous stdout in the next round
+    let mut job = Job::new(pipeline.to_string());
+    let failed = spawn_piped(ctx, &stages, &mut job, !background);
+    if background {
+        add_background(ctx, job);
+        0
+    } else {
+        let status = ctx.jobs.wait_fg(job, false);
+        failed.unwrap_or(status)
+    }
+}
 
-            if let Err(e) = middle_cmd_spawn.wait() {
-                eprintln!("yui: pipe error: {}", e);
+// Run a compound command in the shell with its redirections in place until it's done
+fn run_redirected(ctx: &mut Context, compound: &ast::CompoundCommand, redirs: &Redirections) -> i32 {
+    let saved = match redirs.apply_saved() {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("yui: {}", redirect::io_error_msg(&e));
+            return 1;
+        }
+    };
+    let status = run_compound(ctx, compound);
+    saved.restore();
+    status
+}
+
+fn run_compound(ctx: &mut Context, compound: &ast::CompoundCommand) -> i32 {
+    match compound {
+        // a subshell only gets here once it has been forked
+        ast::CompoundCommand::Subshell(list) | ast::CompoundCommand::Group(list) => run_list(ctx, list),
+        ast::CompoundCommand::If(clause) => run_if(ctx, clause),
+        ast::CompoundCommand::Arith(expr) => match expand::arithmetic(ctx, expr) {
+            Ok(n) if n.is_true() => 0,
+            Ok(_) => 1,
+            Err(e) => {
+                eprintln!("yui: {}", e);
+                1
+            }
+        },
+    }
+}
+
+// Run the body of the first branch whose condition succeeds, the status is 0 if none did
+fn run_if(ctx: &mut Context, clause: &ast::IfClause) -> i32 {
+    for (cond, body) in clause.branches.iter() {
+        run_list(ctx, cond);
+        if ctx.stopping() {
+            return ctx.laststatus;
+        }
+        if ctx.laststatus == 0 {
+            return run_list(ctx, body);
+        }
+    }
+    match &clause.otherwise {
+        Some(body) => run_list(ctx, body),
+        None => 0,
+    }
+}
+
+fn run_builtin(ctx: &mut Context, stage: &Stage, redirs: &Redirections) -> i32 {
+    let (cmd, args) = match stage.words.split_first() {
+        Some(c) => c, // first one will be the command
+        // only assignments and redirections, they have already been done
+        None => return ctx.laststatus,
+    };
+    // point the shell's own fds at the redirections for a moment
+    let saved = match redirs.apply_saved() {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("yui: {}", redirect::io_error_msg(&e));
+            return 1;
+        }
+    };
+    // the assignments only last as long as the builtin
+    let saved_env = assign_temporary(ctx, &stage.env);
+    let status = check_builtins(ctx, cmd, &args.iter().collect::<Vec<&String>>()).unwrap_or(0);
+    for (name, old) in saved_env.into_iter().rev() {
+        match old {
+            Some(var) => ctx.vars.insert(name, var),
+            None => ctx.vars.remove(&name),
+        };
+    }
+    saved.restore();
+    status
+}
+
+// Set the assignments in front of a builtin or function, exported so the commands it runs see them
+// too. Returns what they replaced.
+fn assign_temporary(ctx: &mut Context, env: &[(String, String)]) -> Vec<(String, Option<Var>)> {
+    let mut saved = Vec::new();
+    for (name, value) in env.iter() {
+        saved.push((name.clone(), ctx.vars.get(name).cloned()));
+        // readonly ones have been refused already
+        if ctx.set_var(name, value).is_ok() {
+            if let Some(var) = ctx.vars.get_mut(name) {
+                var.exported = true;
+            }
+        }
+    }
+    saved
+}
+
+// Spawn every stage at once, each one reading from the previous one through a pipe. The processes
+// are added to job, and if the last stage couldn't be started the status to use instead is returned.
+fn spawn_piped(ctx: &mut Context, all: &[Stage], job: &mut Job, foreground: bool) -> Option<i32> {
+    let mut prev_read: Option<File> = None;
+    let last = all.len() - 1;
+    let mut failed = None;
+
+    for (i, stage) in all.iter().enumerate() {
+        let stdin = prev_read.take();
+        let stdout = if i < last {
+            match sys::pipe() {
+                Ok((read, write)) => {
+                    prev_read = Some(read);
+                    Some(write)
+                }
+                Err(e) => {
+                    eprintln!("yui: pipe error: {}", e);
+                    break;
+                }
             }
         } else {
-            // this means we are on the last command
-            let mut last_cmd_spawn = Command::new(iter.next().unwrap())
-                .args(iter)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::inherit())
-                .spawn()
-                .unwrap();
+            None
+        };
+        // a stage that can't start just closes its end of the pipes, so its neighbours see EOF
+        let redirs = match &stage.redirs {
+            Some(r) => r,
+            None => {
+                if i == last {
+                    failed = Some(1);
+                }
+                continue;
+            }
+        };
+        let group = ctx.jobs.group(job.pgid, foreground);
+        match spawn_stage(ctx, stage, redirs, stdin, stdout, prev_read.as_ref(), group) {
+            Ok(pid) => job.add_process(pid, ctx.jobs.control),
+            Err((e, status)) => {
+                eprintln!("yui: {}", e);
+                if i == last {
+                    failed = Some(status);
+                }
+            }
+        }
+    }
+    failed
+}
 
-            store_stdout.unwrap().read_to_end(&mut buf).unwrap();
-            last_cmd_spawn.stdin.take().unwrap().write_all(&buf).unwrap();
+// Start one stage of a pipeline without waiting for it, builtins run in a forked copy of the shell.
+// On failure the message comes back with the status the stage should have.
+fn spawn_stage(
+    ctx: &mut Context,
+    stage: &Stage,
+    redirs: &Redirections,
+    stdin: Option<File>,
+    stdout: Option<File>,
+    next_read: Option<&File>,
+    group: Option<ProcessGroup>,
+) -> Result<pid_t, (String, i32)> {
+    if let Some((cmd, args)) = stage.words.split_first().filter(|(cmd, _)| !runs_in_shell(ctx, cmd)) {
+        let mut command = Command::new(cmd);
+        command.args(args);
+        // only exported variables are passed on, not whatever yui itself was started with
+        command.env_clear();
+        command.envs(ctx.environment());
+        command.envs(stage.env.iter().map(|(name, value)| (name, value)));
+        if let Some(f) = stdin {
+            command.stdin(Stdio::from(f));
+        }
+        if let Some(f) = stdout {
+            command.stdout(Stdio::from(f));
+        }
+        with_job(&mut command, group);
+        with_redirections(&mut command, redirs);
+        return command.spawn().map(|c| c.id() as pid_t).map_err(|e| spawn_error(cmd, &e));
+    }
 
-            if let Err(e) = last_cmd_spawn.wait() {
-                eprintln!("yui: pipe error: {}", e);
+    match sys::fork() {
+        Ok(0) => {
+            // child, never returns
+            if let Some(g) = group {
+                g.join();
+            }
+            jobs::reset_signals();
+            ctx.jobs.forked();
+            if let Some(f) = next_read {
+                sys::close(f.as_raw_fd());
+            }
+            let mut ops = Vec::new();
+            if let Some(f) = &stdin {
+                ops.push(FdOp::Dup(f.as_raw_fd(), 0));
+            }
+            if let Some(f) = &stdout {
+                ops.push(FdOp::Dup(f.as_raw_fd(), 1));
             }
+            ops.extend_from_slice(&redirs.ops);
+            if let Err(e) = redirect::apply(&ops) {
+                eprintln!("yui: {}", redirect::io_error_msg(&e));
+                sys::exit_child(1);
+            }
+            drop(stdin);
+            drop(stdout);
+            assign_temporary(ctx, &stage.env);
+            let mut status = 0;
+            if let Some(compound) = stage.compound {
+                status = run_compound(ctx, compound);
+            } else if let Some((cmd, args)) = stage.words.split_first() {
+                status = check_builtins(ctx, cmd, &args.iter().collect::<Vec<&String>>()).unwrap_or(0);
+            }
+            let _ = io::stdout().flush();
+            sys::exit_child(status);
+        }
+        Ok(pid) => Ok(pid),
+        Err(e) => Err((format!("fork: {}", e), 1)),
+    }
+}
 
-            break;
+// Describe a command that couldn't be executed the way bash does: 127 if it couldn't be found
+// and 126 if it was found but couldn't be run
+fn spawn_error(cmd: &str, e: &io::Error) -> (String, i32) {
+    match e.kind() {
+        ErrorKind::NotFound if !cmd.contains('/') => (format!("{}: command not found", cmd), 127),
+        ErrorKind::NotFound => (format!("{}: No such file or directory", cmd), 127),
+        _ if fs::metadata(cmd).map(|m| m.is_dir()).unwrap_or(false) && cmd.contains('/') => {
+            (format!("{}: Is a directory", cmd), 126)
         }
-        buf = Vec::new(); // clear buffer
+        _ => (format!("{}: {}", cmd, redirect::io_error_msg(e)), 126),
     }
 }
 
-fn spawn_chained(all: &[&[String]]) {
-    for c in all.iter() {
-        let mut iter = c.iter();
-        let spawn = Command::new(iter.next().unwrap()).args(iter).spawn();
-        if let Err(m) = spawn.unwrap().wait() {
-            eprintln!("{}", m);
-            break;
+// Put the child in its job's process group and give it back the default signal handling
+fn with_job(command: &mut Command, group: Option<ProcessGroup>) {
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(g) = group {
+                g.join();
+            }
+            jobs::reset_signals();
+            Ok(())
+        });
+    }
+}
+
+// The redirections are applied in the child, after the pipes have been set up
+fn with_redirections(command: &mut Command, redirs: &Redirections) {
+    if redirs.ops.is_empty() {
+        return;
+    }
+    let ops = redirs.ops.clone();
+    unsafe {
+        command.pre_exec(move || redirect::apply(&ops));
+    }
+}
+
+fn wait_child(pid: pid_t) -> i32 {
+    match sys::wait_pid(pid) {
+        Ok(status) => {
+            if let ProcStatus::Signaled(sig, core) = status {
+                if let Some(msg) = jobs::signal_message(sig, core) {
+                    eprintln!("{}", msg);
+                }
+            }
+            jobs::exit_status(status)
+        }
+        Err(e) => {
+            eprintln!("yui: {}", e);
+            1
         }
     }
 }
 
-fn check_builtins(ctx: &mut Context, c: &str, a: &[&String]) -> bool {
+pub const BUILTINS: &[&str] = &[
+    "alias",
+    "bg",
+    "builtins",
+    "cd",
+    "disown",
+    "echo",
+    "exit",
+    "export",
+    "fg",
+    "functions",
+    "history",
+    "jobs",
+    "local",
+    "readonly",
+    "return",
+    "set",
+    "shift",
+    "test",
+    "type",
+    "unset",
+    "version",
+    "wait",
+    "[",
+];
+
+pub fn is_builtin(c: &str) -> bool {
+    BUILTINS.contains(&c)
+}
+
+fn runs_in_shell(ctx: &Context, c: &str) -> bool {
+    ctx.functions.contains_key(c) || is_builtin(c)
+}
+
+// Deep enough for any sane recursion, shallow enough not to overflow yui's own stack
+const MAX_FUNCTION_DEPTH: usize = 256;
+
+// Run a function with its own positional parameters and local variables
+fn call_function(ctx: &mut Context, def: &ast::FunctionDef, args: &[&String]) -> i32 {
+    if ctx.frames.len() >= MAX_FUNCTION_DEPTH {
+        eprintln!("yui: {}: maximum function nesting level exceeded ({})", def.name, MAX_FUNCTION_DEPTH);
+        return 1;
+    }
+    let saved_args = mem::replace(&mut ctx.args, args.iter().map(|a| a.to_string()).collect());
+    ctx.frames.push(Frame::default());
+    run_list(ctx, &def.body);
+    let status = ctx.returning.take().unwrap_or(ctx.laststatus);
+    if let Some(frame) = ctx.frames.pop() {
+        for (name, var) in frame.saved.into_iter() {
+            match var {
+                Some(var) => ctx.vars.insert(name, var),
+                None => ctx.vars.remove(&name),
+            };
+        }
+    }
+    ctx.args = saved_args;
+    status
+}
+
+// Run builtin c, returning its status or None if there's no such builtin
+fn check_builtins(ctx: &mut Context, c: &str, a: &[&String]) -> Option<i32> {
+    // functions come first, so they can wrap builtins and commands of the same name
+    if let Some(def) = ctx.functions.get(c).cloned() {
+        return Some(call_function(ctx, &def, a));
+    }
     let args = a.to_vec();
-    match c {
-        "cd" => builtins::cd(&args),
+    let status = match c {
+        "jobs" => builtins::jobs(ctx, &args),
+        "fg" => builtins::fg(ctx, &args),
+        "bg" => builtins::bg(ctx, &args),
+        "wait" => builtins::wait(ctx, &args),
+        "disown" => builtins::disown(ctx, &args),
+        "cd" => builtins::cd(ctx, &args),
         "echo" => builtins::echo(&args),
-        "export" => builtins::export(&args),
+        "exit" => builtins::exit(ctx, &args),
+        "export" => builtins::export(ctx, &args),
+        "functions" => builtins::functions(ctx, &args),
+        "local" => builtins::local(ctx, &args),
+        "return" => builtins::return_(ctx, &args),
+        "type" => builtins::type_(ctx, &args),
         "set" => builtins::set(ctx, &args),
         "alias" => builtins::alias(ctx, &args),
-        "history" => builtins::history(&ctx.histfile, &args),
+        "history" => builtins::history(ctx, &args),
+        "readonly" => builtins::readonly(ctx, &args),
+        "shift" => builtins::shift(ctx, &args),
+        "test" => builtins::test(&args, false),
+        "[" => builtins::test(&args, true),
+        "unset" => builtins::unset(ctx, &args),
         "version" => {
-            println!("yui, version 0.0\nA bash-like shell focused on speed and simplicity.\n")
+            println!("yui, version 0.0\nA bash-like shell focused on speed and simplicity.\n");
+            0
         }
-        "builtins" => println!("Builtin commands:\ncd\necho\nversion\nexport\nset\nexit"),
-        _ => return false,
-    }
-    true
-}
-
-fn check_aliases(ctx: &mut Context, c: &String) -> Option<Vec<String>> {
-    //let text = &c.to_string();
-    let aliases = &mut ctx.aliases;
-    if aliases.contains_key(c) {
-        let key = &aliases[c];
-        if let parser::ArgTypes::Norm(c, _) = parser::split_to_args(key.to_string()) {
-            Some(c)
-        } else {
-            None // should never happen
+        "builtins" => {
+            println!("Builtin commands:\n{}", BUILTINS.join("\n"));
+            0
         }
-    } else {
-        //c.to_string()
-        None
-    }
+        _ => return None,
+    };
+    Some(status)
 }
@@ -0,0 +1,209 @@
+// Thin wrappers around the libc calls we need for managing processes and fds
+use std::{
+    ffi::{CStr, CString},
+    fs::File,
+    io,
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+};
+
+use libc::pid_t;
+
+// How a child process ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcStatus {
+    Exited(i32),
+    Signaled(i32, bool), // the signal, and whether it dumped core
+    Stopped(i32),
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+// Returns (read end, write end), both are closed on exec
+pub fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    unsafe {
+        check(libc::pipe(fds.as_mut_ptr()))?;
+        let ends = (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]));
+        check(libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC))?;
+        check(libc::fcntl(fds[1], libc::F_SETFD, libc::FD_CLOEXEC))?;
+        Ok(ends)
+    }
+}
+
+pub fn dup2(src: RawFd, dst: RawFd) -> io::Result<()> {
+    if src != dst {
+        check(unsafe { libc::dup2(src, dst) })?;
+    }
+    Ok(())
+}
+
+// Duplicate fd somewhere above the ones users are likely to redirect, or None if it isn't open
+pub fn dup_high(fd: RawFd) -> Option<RawFd> {
+    check(unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) }).ok()
+}
+
+pub fn close(fd: RawFd) {
+    unsafe {
+        libc::close(fd);
+    }
+}
+
+// Returns 0 in the child and the child's pid in the parent
+pub fn fork() -> io::Result<pid_t> {
+    check(unsafe { libc::fork() })
+}
+
+// Leave a forked child without running any destructors or atexit handlers
+pub fn exit_child(status: i32) -> ! {
+    unsafe { libc::_exit(status) }
+}
+
+pub fn wait_pid(pid: pid_t) -> io::Result<ProcStatus> {
+    waitpid(pid, 0).map(|s| s.unwrap_or(ProcStatus::Exited(0)))
+}
+
+// Like wait_pid but also reports children that stopped, and with block false returns None
+// straight away if pid hasn't changed state
+pub fn wait_job(pid: pid_t, block: bool) -> io::Result<Option<ProcStatus>> {
+    waitpid(pid, if block { libc::WUNTRACED } else { libc::WUNTRACED | libc::WNOHANG })
+}
+
+fn waitpid(pid: pid_t, flags: libc::c_int) -> io::Result<Option<ProcStatus>> {
+    let mut status = 0;
+    loop {
+        match check(unsafe { libc::waitpid(pid, &mut status, flags) }) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    if libc::WIFSTOPPED(status) {
+        Ok(Some(ProcStatus::Stopped(libc::WSTOPSIG(status))))
+    } else if libc::WIFSIGNALED(status) {
+        Ok(Some(ProcStatus::Signaled(libc::WTERMSIG(status), libc::WCOREDUMP(status))))
+    } else {
+        Ok(Some(ProcStatus::Exited(libc::WEXITSTATUS(status))))
+    }
+}
+
+pub fn isatty(fd: RawFd) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+pub fn getpid() -> pid_t {
+    unsafe { libc::getpid() }
+}
+
+pub fn getpgrp() -> pid_t {
+    unsafe { libc::getpgrp() }
+}
+
+// Put pid in the process group pgid, 0 for either means the calling process
+pub fn setpgid(pid: pid_t, pgid: pid_t) -> io::Result<()> {
+    check(unsafe { libc::setpgid(pid, pgid) }).map(|_| ())
+}
+
+pub fn tcgetpgrp(fd: RawFd) -> pid_t {
+    unsafe { libc::tcgetpgrp(fd) }
+}
+
+// Make pgid the foreground process group of the terminal
+pub fn tcsetpgrp(fd: RawFd, pgid: pid_t) -> io::Result<()> {
+    check(unsafe { libc::tcsetpgrp(fd, pgid) }).map(|_| ())
+}
+
+pub fn tcgetattr(fd: RawFd) -> Option<libc::termios> {
+    let mut modes: libc::termios = unsafe { std::mem::zeroed() };
+    check(unsafe { libc::tcgetattr(fd, &mut modes) }).ok().map(|_| modes)
+}
+
+pub fn tcsetattr(fd: RawFd, modes: &libc::termios) {
+    unsafe {
+        libc::tcsetattr(fd, libc::TCSADRAIN, modes);
+    }
+}
+
+// Send sig to pid, or to a whole process group if pid is negative
+pub fn kill(pid: pid_t, sig: libc::c_int) -> io::Result<()> {
+    check(unsafe { libc::kill(pid, sig) }).map(|_| ())
+}
+
+pub fn ignore_signal(sig: libc::c_int) {
+    unsafe {
+        libc::signal(sig, libc::SIG_IGN);
+    }
+}
+
+pub fn default_signal(sig: libc::c_int) {
+    unsafe {
+        libc::signal(sig, libc::SIG_DFL);
+    }
+}
+
+pub fn catch_signal(sig: libc::c_int, handler: extern "C" fn(libc::c_int)) {
+    unsafe {
+        libc::signal(sig, handler as libc::sighandler_t);
+    }
+}
+
+// What a signal is called, eg. "Segmentation fault" for SIGSEGV
+pub fn strsignal(sig: libc::c_int) -> String {
+    let name = unsafe { libc::strsignal(sig) };
+    if name.is_null() {
+        format!("Signal {}", sig)
+    } else {
+        unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned()
+    }
+}
+
+// Whether the real user can access path with mode, one of libc::R_OK, W_OK or X_OK
+pub fn access(path: &str, mode: libc::c_int) -> bool {
+    match CString::new(path) {
+        Ok(p) => unsafe { libc::access(p.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+// Take or drop a lock on a whole file, one of libc::LOCK_SH, LOCK_EX or LOCK_UN. Other processes
+// wait for it, and it goes when the file is closed.
+pub fn flock(file: &File, op: libc::c_int) -> io::Result<()> {
+    loop {
+        match check(unsafe { libc::flock(file.as_raw_fd(), op) }) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result.map(|_| ()),
+        }
+    }
+}
+
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } == -1 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+// Format secs since the epoch in the local timezone with a strftime format
+pub fn strftime(secs: i64, format: &str) -> String {
+    let format = match CString::new(format) {
+        Ok(f) => f,
+        Err(_) => return String::new(),
+    };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let mut buf = [0u8; 128];
+    let len = unsafe {
+        if libc::localtime_r(&(secs as libc::time_t), &mut tm).is_null() {
+            return String::new();
+        }
+        libc::strftime(buf.as_mut_ptr() as *mut libc::c_char, buf.len(), format.as_ptr(), &tm)
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
@@ -0,0 +1,48 @@
+// Tests that run commands, done through the yui binary since forking inside the multithreaded test
+// harness isn't safe
+use std::process::Command;
+
+// Run script with yui -c, returning its output and exit status
+fn yui(script: &str) -> (String, i32) {
+    let out = Command::new(env!("CARGO_BIN_EXE_yui")).args(["-c", script]).output().unwrap();
+    (String::from_utf8_lossy(&out.stdout).into_owned(), out.status.code().unwrap_or(-1))
+}
+
+#[test]
+fn test_temporary_assignments() {
+    let (out, _) = yui("f() { sh -c 'echo \"[$VAR]\"'; }; VAR=x f; f");
+    assert_eq!(out, "[x]\n[]\n");
+    let (out, _) = yui("VAR=old; f() { sh -c 'echo $VAR'; }; VAR=x f; echo $VAR; sh -c 'echo \"[$VAR]\"'");
+    assert_eq!(out, "x\nold\n[]\n");
+    let (out, _) = yui("VAR=x cd /; echo \"[$VAR]\"");
+    assert_eq!(out, "[]\n");
+    let (out, _) = yui("f() { sh -c 'echo $VAR'; }; VAR=piped f | cat");
+    assert_eq!(out, "piped\n");
+}
+
+#[test]
+fn test_command_substitution() {
+    let (out, _) = yui("printf '<%s>' $(printf 'a b') \"$(printf 'a b')\" x$(printf 'a\\n\\n\\n')x");
+    assert_eq!(out, "<a><b><a b><xax>");
+    let (out, _) =
+        yui("printf '<%s>' $(printf '%s ' $(printf nested) \"$(printf ')')\") `printf '%s ' back \\`printf ticks\\``");
+    assert_eq!(out, "<nested><)><back><ticks>");
+    let (out, _) = yui("printf '<%s>' x $(false) y; x=$(false); echo $?");
+    assert_eq!(out, "<x><y>1\n");
+    // a subshell at the start isn't arithmetic, and its variables stay inside it
+    let (out, _) =
+        yui("printf '<%s>' $( (X=in; printf \"$X\") ; printf \"[$X]\" ) $({ printf a; printf b; } | tr a-z A-Z)");
+    assert_eq!(out, "<in[]><AB>");
+    let (out, _) = yui("echo $(( $(printf 2) ** 3 ))");
+    assert_eq!(out, "8\n");
+}
+
+#[test]
+fn test_unfinished_script() {
+    let path = std::env::temp_dir().join(format!("yui_test_unfinished_{}", std::process::id()));
+    std::fs::write(&path, "echo a\nif true; then\n  echo b\n").unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_yui")).arg(&path).output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "a\n");
+    assert_eq!(out.status.code(), Some(2));
+}